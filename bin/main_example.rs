@@ -21,7 +21,6 @@ async fn index(_: Request) -> Response {
     .await
 }
 
-#[cfg(not(release))]
 #[surfer_launch]
 async fn main() {
     println!("Testing the 'surfer' library...");
@@ -0,0 +1,109 @@
+pub mod basic;
+pub mod bearer;
+pub mod htpasswd;
+pub mod jwt;
+
+use crate::logs::Logger;
+use crate::request::{FromRequest, Request};
+use crate::response::{Response, StatusCode};
+use std::collections::HashMap;
+use std::fmt;
+
+pub use basic::BasicAuth;
+pub use bearer::{BearerAuth, BearerError, TokenValidator};
+pub use htpasswd::Htpasswd;
+pub use jwt::{Claims, JwtAuth, JwtKey};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    Basic,
+    Bearer,
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scheme::Basic => f.write_str("Basic"),
+            Scheme::Bearer => f.write_str("Bearer"),
+        }
+    }
+}
+
+// Who sent the request, available to handlers behind `BasicAuth` or `BearerAuth`:
+//
+//     async fn admin(request: Request) -> Response {
+//         let principal = match Principal::from_request(&request) {
+//             Ok(principal) => principal,
+//             Err(response) => return response,
+//         };
+//         Response::text(format!("Hello {}", principal.name))
+//     }
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    // The user name with Basic auth, whatever the validator decides with Bearer auth
+    pub name: String,
+    pub scheme: Scheme,
+    // e.g. the scopes or roles a token grants
+    pub attributes: HashMap<String, String>,
+}
+
+impl Principal {
+    pub fn new(name: &str, scheme: Scheme) -> Principal {
+        Principal {
+            name: name.to_string(),
+            scheme,
+            attributes: HashMap::new(),
+        }
+    }
+
+    pub fn with_attribute(mut self, key: &str, value: &str) -> Principal {
+        self.attributes.insert(key.to_string(), value.to_string());
+        self
+    }
+}
+
+impl FromRequest for Principal {
+    fn from_request(request: &Request) -> Result<Self, Response> {
+        request
+            .extensions
+            .get::<Principal>()
+            .cloned()
+            .ok_or_else(|| {
+                Logger::new().error("No principal found, is an auth middleware missing?");
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .header("Content-Type", "text/plain; charset=utf-8")
+                    .body("500 Internal Server Error")
+            })
+    }
+}
+
+// Credentials of the `Authorization` header if it uses `scheme`, which is case-insensitive
+pub(crate) fn credentials(request: &Request, scheme: Scheme) -> Option<&str> {
+    let (name, credentials) = request.header("Authorization")?.trim().split_once(' ')?;
+    name.eq_ignore_ascii_case(&scheme.to_string())
+        .then(|| credentials.trim())
+}
+
+// Quoted string for challenge parameters (RFC 9110, 5.6.4)
+pub(crate) fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+pub(crate) fn challenge(status_code: StatusCode, www_authenticate: String) -> Response {
+    let mut response = Response::builder()
+        .status(status_code)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(status_code.to_string());
+    response.set_header("WWW-Authenticate", www_authenticate);
+    response
+}
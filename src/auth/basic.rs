@@ -0,0 +1,65 @@
+use crate::auth::{challenge, credentials, quote, Htpasswd, Principal, Scheme};
+use crate::middleware::{Middleware, Next};
+use crate::request::Request;
+use crate::response::{Response, StatusCode};
+use async_std::task;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::sync::Arc;
+
+// HTTP Basic authentication (RFC 7617) against the users of an htpasswd file.
+// Requests without valid credentials get a 401 that makes browsers ask for them:
+//
+//     let users = Htpasswd::load("config/.htpasswd")?;
+//     server.register_group(
+//         RouteGroup::new("/admin")
+//             .with_middleware(BasicAuth::new("Admin area", users))
+//             .route(route!(GET, "/", dashboard)),
+//     );
+//
+// Basic auth sends the password with every request, so only use it over HTTPS.
+pub struct BasicAuth {
+    realm: String,
+    htpasswd: Arc<Htpasswd>,
+}
+
+impl BasicAuth {
+    pub fn new(realm: &str, htpasswd: Htpasswd) -> BasicAuth {
+        BasicAuth {
+            realm: realm.to_string(),
+            htpasswd: Arc::new(htpasswd),
+        }
+    }
+
+    fn unauthorized(&self) -> Response {
+        challenge(
+            StatusCode::UNAUTHORIZED,
+            format!("Basic realm={}, charset=\"UTF-8\"", quote(&self.realm)),
+        )
+    }
+}
+
+// User name and password of `Basic base64(user:password)`
+fn decode(credentials: &str) -> Option<(String, String)> {
+    let decoded = String::from_utf8(STANDARD.decode(credentials).ok()?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+impl Middleware for BasicAuth {
+    async fn handle(&self, mut request: Request, next: Next<'_>) -> Response {
+        let Some((user, password)) = credentials(&request, Scheme::Basic).and_then(decode) else {
+            return self.unauthorized();
+        };
+        let htpasswd = self.htpasswd.clone();
+        let verify_user = user.clone();
+        let verified = task::spawn_blocking(move || htpasswd.verify(&verify_user, &password)).await;
+        if !verified {
+            return self.unauthorized();
+        }
+        request
+            .extensions
+            .insert(Principal::new(&user, Scheme::Basic));
+        next.run(request).await
+    }
+}
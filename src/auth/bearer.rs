@@ -0,0 +1,117 @@
+use crate::auth::{challenge, credentials, quote, Principal, Scheme};
+use crate::middleware::{Middleware, Next};
+use crate::request::Request;
+use crate::response::{Response, StatusCode};
+use std::fmt;
+use std::future::Future;
+
+// Why a bearer token was rejected, sent to the client in the `WWW-Authenticate` challenge
+// along with the description (RFC 6750, 3.1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BearerError {
+    // The request is malformed, e.g. the `Authorization` header has no token
+    InvalidRequest(String),
+    // The token is expired, revoked, malformed or invalid for other reasons
+    InvalidToken(String),
+    // The token is valid but doesn't grant access to this resource
+    InsufficientScope(String),
+}
+
+impl BearerError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            BearerError::InvalidRequest(_) => "invalid_request",
+            BearerError::InvalidToken(_) => "invalid_token",
+            BearerError::InsufficientScope(_) => "insufficient_scope",
+        }
+    }
+
+    pub fn description(&self) -> &str {
+        match self {
+            BearerError::InvalidRequest(description)
+            | BearerError::InvalidToken(description)
+            | BearerError::InsufficientScope(description) => description,
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            BearerError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            BearerError::InvalidToken(_) => StatusCode::UNAUTHORIZED,
+            BearerError::InsufficientScope(_) => StatusCode::FORBIDDEN,
+        }
+    }
+}
+
+impl fmt::Display for BearerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code(), self.description())
+    }
+}
+
+impl std::error::Error for BearerError {}
+
+// Decides whether a bearer token is valid and whom it belongs to. Closures work as well:
+//
+//     BearerAuth::new("api", |token: &str| match tokens.get(token) {
+//         Some(name) => Ok(Principal::new(name, Scheme::Bearer)),
+//         None => Err(BearerError::InvalidToken("unknown token".to_string())),
+//     })
+pub trait TokenValidator: Send + Sync {
+    fn validate(&self, token: &str) -> impl Future<Output = Result<Principal, BearerError>> + Send;
+}
+
+impl<F> TokenValidator for F
+where
+    F: Fn(&str) -> Result<Principal, BearerError> + Send + Sync,
+{
+    async fn validate(&self, token: &str) -> Result<Principal, BearerError> {
+        self(token)
+    }
+}
+
+// Bearer token authentication (RFC 6750) with the `Authorization: Bearer <token>` header
+pub struct BearerAuth<V> {
+    realm: String,
+    validator: V,
+}
+
+impl<V: TokenValidator> BearerAuth<V> {
+    pub fn new(realm: &str, validator: V) -> BearerAuth<V> {
+        BearerAuth {
+            realm: realm.to_string(),
+            validator,
+        }
+    }
+}
+
+impl<V: TokenValidator> Middleware for BearerAuth<V> {
+    async fn handle(&self, mut request: Request, next: Next<'_>) -> Response {
+        let principal = match credentials(&request, Scheme::Bearer) {
+            Some("") => Err(BearerError::InvalidRequest("missing token".to_string())),
+            Some(token) => self.validator.validate(token).await,
+            // Clients that didn't try to authenticate just get the challenge
+            None => return bearer_challenge(&self.realm, None),
+        };
+        match principal {
+            Ok(principal) => {
+                request.extensions.insert(principal);
+                next.run(request).await
+            }
+            Err(err) => bearer_challenge(&self.realm, Some(&err)),
+        }
+    }
+}
+
+pub(crate) fn bearer_challenge(realm: &str, err: Option<&BearerError>) -> Response {
+    let mut www_authenticate = format!("Bearer realm={}", quote(realm));
+    let Some(err) = err else {
+        return challenge(StatusCode::UNAUTHORIZED, www_authenticate);
+    };
+    www_authenticate.push_str(&format!(
+        ", error=\"{}\", error_description={}",
+        err.code(),
+        quote(err.description())
+    ));
+    challenge(err.status_code(), www_authenticate)
+}
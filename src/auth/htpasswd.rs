@@ -0,0 +1,144 @@
+use crate::utils::constant_time_eq;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+// Bcrypt hash of a password no user has, with cost 5 like `htpasswd -B` uses by default
+const DUMMY_HASH: &str = "$2y$05$V1FPHNTGllQwacowWhOdA.CaTQ3sm817TqK4iQvFkhQduzjgXxgKC";
+
+#[derive(Debug, Clone)]
+enum PasswordHash {
+    // `$2y$`, `$2b$` and `$2a$`, as created by `htpasswd -B`
+    Bcrypt(String),
+    // `{SHA}` followed by the base64 encoded SHA-1 digest, as created by `htpasswd -s`
+    Sha1(Vec<u8>),
+}
+
+// Users and password hashes in the format of Apache's htpasswd files, one `user:hash` per line.
+// Only bcrypt and SHA-1 hashes are supported, files with other hashes fail to load.
+#[derive(Debug, Clone, Default)]
+pub struct Htpasswd {
+    users: HashMap<String, PasswordHash>,
+}
+
+impl Htpasswd {
+    pub fn new() -> Htpasswd {
+        Htpasswd::default()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Htpasswd> {
+        Htpasswd::parse(&std::fs::read_to_string(path)?)
+    }
+
+    // Empty lines and lines starting with `#` are skipped
+    pub fn parse(contents: &str) -> io::Result<Htpasswd> {
+        let mut htpasswd = Htpasswd::new();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |message: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("htpasswd line {}: {}", index + 1, message),
+                )
+            };
+            let (user, hash) = line.split_once(':').ok_or_else(|| invalid("missing ':'"))?;
+            htpasswd
+                .add(user, hash)
+                .map_err(|err| invalid(&err.to_string()))?;
+        }
+        Ok(htpasswd)
+    }
+
+    // Adds a user with a hash in htpasswd format, e.g. `$2y$05$...` or `{SHA}...`
+    pub fn add(&mut self, user: &str, hash: &str) -> io::Result<()> {
+        let hash = if hash.starts_with("$2") {
+            PasswordHash::Bcrypt(hash.to_string())
+        } else if let Some(digest) = hash.strip_prefix("{SHA}") {
+            let digest = STANDARD
+                .decode(digest)
+                .ok()
+                .filter(|digest| digest.len() == 20)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid SHA-1 hash"))?;
+            PasswordHash::Sha1(digest)
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported hash, use bcrypt or SHA-1",
+            ));
+        };
+        self.users.insert(user.to_string(), hash);
+        Ok(())
+    }
+
+    pub fn contains(&self, user: &str) -> bool {
+        self.users.contains_key(user)
+    }
+
+    // Bcrypt is slow on purpose, so don't call this on the async executor directly
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        match self.users.get(user) {
+            Some(PasswordHash::Bcrypt(hash)) => bcrypt::verify(password, hash).unwrap_or(false),
+            Some(PasswordHash::Sha1(digest)) => {
+                constant_time_eq(&Sha1::digest(password.as_bytes()), digest)
+            }
+            None => {
+                // Takes as long as checking a real bcrypt hash, so unknown users
+                // can't be told apart from wrong passwords by timing
+                let _ = bcrypt::verify(password, DUMMY_HASH);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "secret" as bcrypt and SHA-1 hash
+    const FILE: &str = "\
+# users
+alice:$2b$05$EQ9N0Kkx7ol.4mqkS6ypve3oFPVnjTfuhgxjHfV6CsQmRBcc9yDfW
+
+bob:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=
+";
+
+    #[test]
+    fn verifies_bcrypt_lines() {
+        let htpasswd = Htpasswd::parse(FILE).unwrap();
+        assert!(htpasswd.verify("alice", "secret"));
+        assert!(!htpasswd.verify("alice", "Secret"));
+    }
+
+    #[test]
+    fn verifies_sha1_lines() {
+        let htpasswd = Htpasswd::parse(FILE).unwrap();
+        assert!(htpasswd.verify("bob", "secret"));
+        assert!(!htpasswd.verify("bob", "secret "));
+    }
+
+    #[test]
+    fn rejects_unknown_users() {
+        let htpasswd = Htpasswd::parse(FILE).unwrap();
+        assert!(!htpasswd.contains("carol"));
+        assert!(!htpasswd.verify("carol", "secret"));
+        assert!(bcrypt::verify("", DUMMY_HASH).is_ok());
+    }
+
+    #[test]
+    fn rejects_unsupported_lines() {
+        let error = Htpasswd::parse("alice:secret").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "htpasswd line 1: unsupported hash, use bcrypt or SHA-1"
+        );
+        assert!(Htpasswd::parse("alice").is_err());
+        assert!(Htpasswd::parse("alice:{SHA}short").is_err());
+    }
+}
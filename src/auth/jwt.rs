@@ -0,0 +1,418 @@
+use crate::auth::bearer::bearer_challenge;
+use crate::auth::{credentials, BearerError, Principal, Scheme};
+use crate::logs::Logger;
+use crate::middleware::{Middleware, Next};
+use crate::request::{FromRequest, Request};
+use crate::response::{Response, StatusCode};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::jwk::{AlgorithmParameters, Jwk, KeyAlgorithm};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::io;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::time::Duration;
+
+pub const DEFAULT_LEEWAY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JwtAlgorithm {
+    HS256,
+    RS256,
+}
+
+impl JwtAlgorithm {
+    fn algorithm(&self) -> Algorithm {
+        match self {
+            JwtAlgorithm::HS256 => Algorithm::HS256,
+            JwtAlgorithm::RS256 => Algorithm::RS256,
+        }
+    }
+}
+
+// A key tokens can be signed with. Tokens are only checked against keys of the algorithm
+// in their header, and against the key with the same ID if the header names one.
+#[derive(Clone)]
+pub struct JwtKey {
+    id: Option<String>,
+    algorithm: JwtAlgorithm,
+    key: DecodingKey,
+}
+
+impl JwtKey {
+    pub fn hs256(secret: &[u8]) -> JwtKey {
+        JwtKey {
+            id: None,
+            algorithm: JwtAlgorithm::HS256,
+            key: DecodingKey::from_secret(secret),
+        }
+    }
+
+    // Public key in PEM format, either PKCS#1 (`BEGIN RSA PUBLIC KEY`) or SPKI (`BEGIN PUBLIC KEY`)
+    pub fn rs256_pem(pem: &[u8]) -> io::Result<JwtKey> {
+        let key = DecodingKey::from_rsa_pem(pem)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(JwtKey {
+            id: None,
+            algorithm: JwtAlgorithm::RS256,
+            key,
+        })
+    }
+
+    // The `kid` tokens signed with this key carry in their header
+    pub fn with_id(mut self, id: &str) -> JwtKey {
+        self.id = Some(id.to_string());
+        self
+    }
+
+    // The HS256 and RS256 keys of a JSON Web Key Set (RFC 7517), e.g. a copy of the
+    // `jwks_uri` document of an identity provider. Keys of other types are skipped.
+    pub fn from_jwks(jwks: &str) -> io::Result<Vec<JwtKey>> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let jwks: Value = serde_json::from_str(jwks).map_err(|err| invalid(err.to_string()))?;
+        let keys = jwks
+            .get("keys")
+            .and_then(Value::as_array)
+            .ok_or_else(|| invalid("JWKS has no \"keys\" array".to_string()))?;
+        Ok(keys
+            .iter()
+            .filter_map(|jwk| serde_json::from_value::<Jwk>(jwk.clone()).ok())
+            .filter_map(|jwk| JwtKey::from_jwk(&jwk))
+            .collect())
+    }
+
+    pub fn from_jwks_file(path: impl AsRef<Path>) -> io::Result<Vec<JwtKey>> {
+        JwtKey::from_jwks(&std::fs::read_to_string(path)?)
+    }
+
+    fn from_jwk(jwk: &Jwk) -> Option<JwtKey> {
+        let algorithm = match (&jwk.common.key_algorithm, &jwk.algorithm) {
+            (Some(KeyAlgorithm::HS256), AlgorithmParameters::OctetKey(_))
+            | (None, AlgorithmParameters::OctetKey(_)) => JwtAlgorithm::HS256,
+            (Some(KeyAlgorithm::RS256), AlgorithmParameters::RSA(_))
+            | (None, AlgorithmParameters::RSA(_)) => JwtAlgorithm::RS256,
+            _ => return None,
+        };
+        Some(JwtKey {
+            id: jwk.common.key_id.clone(),
+            algorithm,
+            key: DecodingKey::from_jwk(jwk).ok()?,
+        })
+    }
+}
+
+// Claims of a validated token, available to handlers behind `JwtAuth`:
+//
+//     #[derive(Clone, Deserialize)]
+//     struct User {
+//         sub: String,
+//         roles: Vec<String>,
+//     }
+//
+//     async fn profile(request: Request) -> Response {
+//         let Claims(user) = match Claims::<User>::from_request(&request) { ... };
+//     }
+#[derive(Debug, Clone)]
+pub struct Claims<C>(pub C);
+
+impl<C: Clone + Send + Sync + 'static> FromRequest for Claims<C> {
+    fn from_request(request: &Request) -> Result<Self, Response> {
+        request
+            .extensions
+            .get::<Claims<C>>()
+            .cloned()
+            .ok_or_else(|| {
+                Logger::new().error("No claims found, is the JwtAuth middleware missing?");
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .header("Content-Type", "text/plain; charset=utf-8")
+                    .body("500 Internal Server Error")
+            })
+    }
+}
+
+// Bearer authentication with JSON Web Tokens (RFC 7519) signed with HS256 or RS256.
+// Besides the signature, `exp` and `sub` are required and checked, as are `nbf` if present,
+// and `iss` and `aud` if issuers and audiences are configured. Tokens that fail get a 401
+// saying why.
+//
+//     let keys = JwtKey::from_jwks_file("config/jwks.json")?;
+//     server.add_middleware(
+//         JwtAuth::<User>::new("api")
+//             .with_keys(keys)
+//             .with_issuer("https://id.example.com")
+//             .with_audience("orders"),
+//     );
+//
+// The claims are available with `Claims<C>` and the subject (`sub`) as `Principal`.
+pub struct JwtAuth<C = Value> {
+    realm: String,
+    keys: Vec<JwtKey>,
+    issuers: Vec<String>,
+    audiences: Vec<String>,
+    leeway: Duration,
+    claims: PhantomData<fn() -> C>,
+}
+
+impl<C> JwtAuth<C>
+where
+    C: DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    pub fn new(realm: &str) -> JwtAuth<C> {
+        JwtAuth {
+            realm: realm.to_string(),
+            keys: Vec::new(),
+            issuers: Vec::new(),
+            audiences: Vec::new(),
+            leeway: DEFAULT_LEEWAY,
+            claims: PhantomData,
+        }
+    }
+
+    pub fn with_key(mut self, key: JwtKey) -> JwtAuth<C> {
+        self.keys.push(key);
+        self
+    }
+
+    pub fn with_keys(mut self, keys: Vec<JwtKey>) -> JwtAuth<C> {
+        self.keys.extend(keys);
+        self
+    }
+
+    // Tokens have to be issued by one of the configured issuers
+    pub fn with_issuer(mut self, issuer: &str) -> JwtAuth<C> {
+        self.issuers.push(issuer.to_string());
+        self
+    }
+
+    // Tokens have to be meant for one of the configured audiences
+    pub fn with_audience(mut self, audience: &str) -> JwtAuth<C> {
+        self.audiences.push(audience.to_string());
+        self
+    }
+
+    // How far the clocks of the issuer and this server may be apart when checking `exp` and `nbf`
+    pub fn with_leeway(mut self, leeway: Duration) -> JwtAuth<C> {
+        self.leeway = leeway;
+        self
+    }
+
+    // Checks the token and returns its claims, along with the principal it identifies
+    pub fn validate(&self, token: &str) -> Result<(C, Principal), BearerError> {
+        let invalid = |description: &str| BearerError::InvalidToken(description.to_string());
+        let header = jsonwebtoken::decode_header(token).map_err(|_| invalid("malformed token"))?;
+        // The algorithm is taken from the key, never from the token, so a token can't
+        // e.g. pass an RSA public key off as HMAC secret
+        let mut keys = self
+            .keys
+            .iter()
+            .filter(|key| key.algorithm.algorithm() == header.alg)
+            .filter(|key| header.kid.is_none() || key.id == header.kid)
+            .peekable();
+        if keys.peek().is_none() {
+            return Err(invalid("no key found for the token"));
+        }
+
+        let mut error = invalid("invalid signature");
+        for key in keys {
+            match jsonwebtoken::decode::<Value>(token, &key.key, &self.validation(key)) {
+                Ok(data) => return self.claims(data.claims),
+                Err(err) if *err.kind() == ErrorKind::InvalidSignature => continue,
+                Err(err) => {
+                    error = invalid(&describe(err.kind()));
+                    break;
+                }
+            }
+        }
+        Err(error)
+    }
+
+    fn validation(&self, key: &JwtKey) -> Validation {
+        let mut validation = Validation::new(key.algorithm.algorithm());
+        validation.leeway = self.leeway.as_secs();
+        validation.validate_nbf = true;
+        let mut required = vec!["exp", "sub"];
+        if !self.issuers.is_empty() {
+            validation.set_issuer(&self.issuers);
+            required.push("iss");
+        }
+        if self.audiences.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&self.audiences);
+            required.push("aud");
+        }
+        validation.set_required_spec_claims(&required);
+        validation
+    }
+
+    fn claims(&self, claims: Value) -> Result<(C, Principal), BearerError> {
+        let invalid = |description: &str| BearerError::InvalidToken(description.to_string());
+        let subject = claims
+            .get("sub")
+            .and_then(Value::as_str)
+            .filter(|subject| !subject.is_empty())
+            .ok_or_else(|| invalid("missing claim 'sub'"))?;
+        let principal = Principal::new(subject, Scheme::Bearer);
+        // The details would tell clients about the claims type, they only go to the log
+        let claims = serde_json::from_value(claims).map_err(|err| {
+            Logger::new().info(&format!("Invalid token claims: {}", err));
+            invalid("invalid claims")
+        })?;
+        Ok((claims, principal))
+    }
+}
+
+fn describe(kind: &ErrorKind) -> String {
+    match kind {
+        ErrorKind::ExpiredSignature => "token expired".to_string(),
+        ErrorKind::ImmatureSignature => "token not valid yet".to_string(),
+        ErrorKind::InvalidIssuer => "invalid issuer".to_string(),
+        ErrorKind::InvalidAudience => "invalid audience".to_string(),
+        ErrorKind::InvalidSignature => "invalid signature".to_string(),
+        ErrorKind::InvalidAlgorithm => "unexpected algorithm".to_string(),
+        ErrorKind::MissingRequiredClaim(claim) => format!("missing claim '{}'", claim),
+        _ => "malformed token".to_string(),
+    }
+}
+
+impl<C> Middleware for JwtAuth<C>
+where
+    C: DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    async fn handle(&self, mut request: Request, next: Next<'_>) -> Response {
+        let result = match credentials(&request, Scheme::Bearer) {
+            Some("") => Err(BearerError::InvalidRequest("missing token".to_string())),
+            Some(token) => self.validate(token),
+            None => return bearer_challenge(&self.realm, None),
+        };
+        match result {
+            Ok((claims, principal)) => {
+                request.extensions.insert(Claims(claims));
+                request.extensions.insert(principal);
+                next.run(request).await
+            }
+            Err(err) => bearer_challenge(&self.realm, Some(&err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde::Deserialize;
+    use serde_json::json;
+
+    const SECRET: &[u8] = b"a secret that is long enough for HS256";
+
+    const PUBLIC_KEY: &str = "-----BEGIN PUBLIC KEY-----
+MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEAuccXEm7EDvS3FLCoZ18G
+eKlKo4VtogW/piUkIw0Scb0hKcd+RsrYB/8l16XmKR2yYirG9NgKyaV9/vsiv7FF
+/n73Cesa3dOUEJKSwd2ZV1xt1/d3rgjwzM9SRP03RAmRUAWCD656XPsMh83ZbMdU
+/D3CQAviJatwTnDwycu0Cvxnn5MVBrjyfqDfe9YjPLBNGb0SPqG+EyDg3oPLOHZS
+9c7+ZoLftq8vwYdwXPHfNXkKhybfH4VD4Wr5cg1y2uTbwzws5eOIoCLXaHBweHDX
+b48iCzqPvrjm0u3b7uw3Ld/OrGqSvA79Nftafbq7G9NyqkUySt0b7UwD76n5r8J2
+pwIDAQAB
+-----END PUBLIC KEY-----
+";
+
+    fn token(claims: Value, secret: &[u8]) -> String {
+        jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(secret),
+        )
+        .unwrap()
+    }
+
+    fn claims(offset: i64) -> Value {
+        let exp = jsonwebtoken::get_current_timestamp() as i64 + offset;
+        json!({ "sub": "alice", "exp": exp, "iss": "https://id.example.com", "aud": "orders" })
+    }
+
+    fn auth() -> JwtAuth {
+        JwtAuth::new("api")
+            .with_key(JwtKey::hs256(SECRET))
+            .with_issuer("https://id.example.com")
+            .with_audience("orders")
+    }
+
+    fn invalid(description: &str) -> BearerError {
+        BearerError::InvalidToken(description.to_string())
+    }
+
+    #[test]
+    fn accepts_valid_token() {
+        let (claims, principal) = auth().validate(&token(claims(3600), SECRET)).unwrap();
+        assert_eq!(principal.name, "alice");
+        assert_eq!(claims["aud"], "orders");
+    }
+
+    #[test]
+    fn rejects_expired_token() {
+        let result = auth().validate(&token(claims(-3600), SECRET));
+        assert_eq!(result.unwrap_err(), invalid("token expired"));
+        // Within the leeway for clock skew
+        assert!(auth().validate(&token(claims(-30), SECRET)).is_ok());
+    }
+
+    #[test]
+    fn rejects_wrong_issuer_and_audience() {
+        let mut wrong_issuer = claims(3600);
+        wrong_issuer["iss"] = json!("https://evil.example.com");
+        let result = auth().validate(&token(wrong_issuer, SECRET));
+        assert_eq!(result.unwrap_err(), invalid("invalid issuer"));
+
+        let mut wrong_audience = claims(3600);
+        wrong_audience["aud"] = json!("billing");
+        let result = auth().validate(&token(wrong_audience, SECRET));
+        assert_eq!(result.unwrap_err(), invalid("invalid audience"));
+    }
+
+    #[test]
+    fn rejects_missing_claims() {
+        for claim in ["exp", "sub", "iss", "aud"] {
+            let mut claims = claims(3600);
+            claims.as_object_mut().unwrap().remove(claim);
+            let result = auth().validate(&token(claims, SECRET));
+            assert_eq!(
+                result.unwrap_err(),
+                invalid(&format!("missing claim '{}'", claim))
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_signature() {
+        let result = auth().validate(&token(claims(3600), b"another secret of enough length"));
+        assert_eq!(result.unwrap_err(), invalid("invalid signature"));
+        assert_eq!(
+            auth().validate("abc").unwrap_err(),
+            invalid("malformed token")
+        );
+    }
+
+    #[test]
+    fn rejects_algorithm_mismatch() {
+        // HS256 signed with the RSA public key, which must not pass as HMAC secret
+        let auth = JwtAuth::<Value>::new("api")
+            .with_key(JwtKey::rs256_pem(PUBLIC_KEY.as_bytes()).unwrap());
+        let result = auth.validate(&token(claims(3600), PUBLIC_KEY.as_bytes()));
+        assert_eq!(result.unwrap_err(), invalid("no key found for the token"));
+    }
+
+    #[test]
+    fn hides_claims_type_errors() {
+        #[allow(dead_code)]
+        #[derive(Debug, Clone, Deserialize)]
+        struct User {
+            sub: String,
+            roles: Vec<String>,
+        }
+        let auth = JwtAuth::<User>::new("api").with_key(JwtKey::hs256(SECRET));
+        let result = auth.validate(&token(claims(3600), SECRET));
+        assert_eq!(result.unwrap_err(), invalid("invalid claims"));
+    }
+}
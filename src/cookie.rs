@@ -1,14 +1,99 @@
-#[derive(Debug)]
+pub mod key;
+pub mod private;
+pub mod signed;
+
+use crate::request::{FromRequest, Request};
+use crate::response::Response;
+use crate::utils::{format_http_date, parse_http_date};
+use chrono::{DateTime, Utc};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use std::fmt;
+use std::str::FromStr;
+
+pub use key::{Key, KeyRing};
+pub use private::PrivateJar;
+pub use signed::SignedJar;
+
+// Everything that isn't a cookie-octet (RFC 6265, 4.1.1), plus `%` so decoding is unambiguous
+const COOKIE_VALUE: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b',')
+    .add(b';')
+    .add(b'\\')
+    .add(b'%');
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    // Browsers only accept this together with `Secure`
+    None,
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        })
+    }
+}
+
+impl FromStr for SameSite {
+    type Err = CookieError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "strict" => Ok(SameSite::Strict),
+            "lax" => Ok(SameSite::Lax),
+            "none" => Ok(SameSite::None),
+            _ => Err(CookieError::InvalidAttribute("SameSite")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CookieError {
+    // The `Set-Cookie` string has no `name=value` pair
+    Malformed,
+    // Names have to be a token (RFC 9110, 5.6.2)
+    InvalidName(String),
+    InvalidAttribute(&'static str),
+    SameSiteNoneWithoutSecure,
+}
+
+impl fmt::Display for CookieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CookieError::Malformed => f.write_str("malformed cookie"),
+            CookieError::InvalidName(name) => write!(f, "invalid cookie name '{}'", name),
+            CookieError::InvalidAttribute(attribute) => {
+                write!(f, "invalid cookie attribute '{}'", attribute)
+            }
+            CookieError::SameSiteNoneWithoutSecure => {
+                f.write_str("cookies with SameSite=None have to be Secure")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CookieError {}
+
+// Values are percent-encoded when sent and decoded when parsed,
+// so they may contain anything, including `;` and spaces
+#[derive(Debug, Clone, PartialEq)]
 pub struct Cookie {
     pub name: String,
     pub value: String,
-    pub expires: Option<String>,
+    pub expires: Option<DateTime<Utc>>,
     pub max_age: Option<i64>,
     pub domain: Option<String>,
     pub path: Option<String>,
     pub secure: Option<bool>,
     pub http_only: Option<bool>,
-    pub same_site: Option<String>,
+    pub same_site: Option<SameSite>,
 }
 
 impl Cookie {
@@ -26,8 +111,47 @@ impl Cookie {
         }
     }
 
-    pub fn with_expires(mut self, expires: &str) -> Cookie {
-        self.expires = Some(expires.to_string());
+    // Parses a `Set-Cookie` header value, e.g. `id=a3fWa; Max-Age=2592000; Secure`.
+    // Unknown attributes are ignored, invalid ones are an error.
+    pub fn parse(set_cookie: &str) -> Result<Cookie, CookieError> {
+        let mut parts = set_cookie.split(';');
+        let (name, value) = parts
+            .next()
+            .and_then(|pair| pair.split_once('='))
+            .ok_or(CookieError::Malformed)?;
+        let mut cookie = Cookie::new(name.trim(), &decode_value(value));
+
+        for attribute in parts {
+            let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "expires" => {
+                    cookie.expires = Some(
+                        parse_http_date(value).ok_or(CookieError::InvalidAttribute("Expires"))?,
+                    )
+                }
+                "max-age" => {
+                    cookie.max_age = Some(
+                        value
+                            .parse()
+                            .map_err(|_| CookieError::InvalidAttribute("Max-Age"))?,
+                    )
+                }
+                // A leading dot is ignored by clients (RFC 6265, 5.2.3)
+                "domain" => cookie.domain = Some(value.trim_start_matches('.').to_string()),
+                "path" => cookie.path = Some(value.to_string()),
+                "secure" => cookie.secure = Some(true),
+                "httponly" => cookie.http_only = Some(true),
+                "samesite" => cookie.same_site = Some(value.parse()?),
+                _ => {}
+            }
+        }
+        cookie.validate()?;
+        Ok(cookie)
+    }
+
+    pub fn with_expires(mut self, expires: DateTime<Utc>) -> Cookie {
+        self.expires = Some(expires);
         self
     }
 
@@ -56,38 +180,352 @@ impl Cookie {
         self
     }
 
-    pub fn with_same_site(mut self, same_site: &str) -> Cookie {
-        self.same_site = Some(same_site.to_string());
+    pub fn with_same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
         self
     }
 
-    pub fn to_string(&self) -> String {
-        let mut cookie = format!("{}={}", self.name, self.value);
-        if let Some(expires) = &self.expires {
-            cookie.push_str(&format!("; Expires={}", expires));
+    // Checks everything that can't be encoded. Invalid cookies are never sent,
+    // since they could inject attributes or be rejected by browsers.
+    pub fn validate(&self) -> Result<(), CookieError> {
+        if self.name.is_empty() || !self.name.bytes().all(is_token_char) {
+            return Err(CookieError::InvalidName(self.name.clone()));
+        }
+        // Attribute values end at the next `;` (RFC 6265, 4.1.1)
+        let is_attribute_value = |value: &str| {
+            value
+                .bytes()
+                .all(|byte| !byte.is_ascii_control() && byte != b';')
+        };
+        if !self.domain.as_deref().is_none_or(is_attribute_value) {
+            return Err(CookieError::InvalidAttribute("Domain"));
+        }
+        if !self.path.as_deref().is_none_or(is_attribute_value) {
+            return Err(CookieError::InvalidAttribute("Path"));
+        }
+        if self.same_site == Some(SameSite::None) && self.secure != Some(true) {
+            return Err(CookieError::SameSiteNoneWithoutSecure);
+        }
+        Ok(())
+    }
+
+    // Cookies are identified by name, path and domain, the same name may exist with different paths
+    pub fn is_same(&self, other: &Cookie) -> bool {
+        self.name == other.name && self.path == other.path && self.domain == other.domain
+    }
+
+    // Whether the cookie tells the client to delete it
+    pub fn is_removal(&self) -> bool {
+        self.max_age.is_some_and(|max_age| max_age <= 0)
+    }
+
+    // Cookie that makes the client delete this one, path and domain have to match
+    pub fn into_removal(mut self) -> Cookie {
+        self.value = String::new();
+        self.max_age = Some(0);
+        self.expires = Some(DateTime::UNIX_EPOCH);
+        self
+    }
+}
+
+fn is_token_char(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+fn decode_value(value: &str) -> String {
+    let value = value.trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value);
+    percent_decode_str(value).decode_utf8_lossy().to_string()
+}
+
+// Parses the `name=value` pairs of a `Cookie` request header, skipping malformed ones
+pub fn parse_cookie_header(header: &str) -> Vec<Cookie> {
+    header
+        .split(';')
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            let name = name.trim();
+            if name.is_empty() {
+                return None;
+            }
+            Some(Cookie::new(name, &decode_value(value)))
+        })
+        .collect()
+}
+
+// Cookies of a request along with the changes made while handling it.
+// Pass the jar to `Response::with_cookie_jar` to send a `Set-Cookie` header for every change.
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    original: Vec<Cookie>,
+    delta: Vec<Cookie>,
+}
+
+impl CookieJar {
+    pub fn new() -> CookieJar {
+        CookieJar::default()
+    }
+
+    pub fn from_cookies(cookies: Vec<Cookie>) -> CookieJar {
+        CookieJar {
+            original: cookies,
+            delta: Vec::new(),
+        }
+    }
+
+    // Current cookie with this name, taking additions and removals into account
+    pub fn get(&self, name: &str) -> Option<&Cookie> {
+        match self.delta.iter().rev().find(|cookie| cookie.name == name) {
+            Some(cookie) if cookie.is_removal() => None,
+            Some(cookie) => Some(cookie),
+            None => self.original.iter().find(|cookie| cookie.name == name),
+        }
+    }
+
+    // Replaces an earlier change of the same cookie
+    pub fn add(&mut self, cookie: Cookie) {
+        self.delta.retain(|changed| !changed.is_same(&cookie));
+        self.delta.push(cookie);
+    }
+
+    // Path and domain of `cookie` have to match the ones it was set with
+    pub fn remove(&mut self, cookie: Cookie) {
+        self.delta.retain(|changed| !changed.is_same(&cookie));
+        // Cookies the client never sent don't have to be removed, only the pending addition
+        if self
+            .original
+            .iter()
+            .any(|original| original.name == cookie.name)
+        {
+            self.delta.push(cookie.into_removal());
+        }
+    }
+
+    // Current cookies, i.e. the ones sent by the client plus additions minus removals
+    pub fn iter(&self) -> impl Iterator<Item = &Cookie> {
+        let mut names: Vec<&str> = Vec::new();
+        self.delta
+            .iter()
+            .rev()
+            .chain(self.original.iter())
+            .filter(move |cookie| {
+                if names.contains(&cookie.name.as_str()) {
+                    return false;
+                }
+                names.push(&cookie.name);
+                true
+            })
+            .filter(|cookie| !cookie.is_removal())
+    }
+
+    // Cookies added through the returned jar are signed, reading them verifies the signature
+    pub fn signed<'a>(&'a mut self, keys: &'a KeyRing) -> SignedJar<'a> {
+        SignedJar::new(self, keys)
+    }
+
+    // Cookies added through the returned jar are encrypted, reading them decrypts them
+    pub fn private<'a>(&'a mut self, keys: &'a KeyRing) -> PrivateJar<'a> {
+        PrivateJar::new(self, keys)
+    }
+
+    // Cookies that have been added or removed, each needs a `Set-Cookie` header
+    pub fn delta(&self) -> impl Iterator<Item = &Cookie> {
+        self.delta.iter()
+    }
+}
+
+impl FromRequest for CookieJar {
+    fn from_request(request: &Request) -> Result<Self, Response> {
+        Ok(CookieJar::from_cookies(request.cookies()))
+    }
+}
+
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}={}",
+            self.name,
+            utf8_percent_encode(&self.value, COOKIE_VALUE)
+        )?;
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", format_http_date(expires))?;
         }
         if let Some(max_age) = &self.max_age {
-            cookie.push_str(&format!("; Max-Age={}", max_age));
+            write!(f, "; Max-Age={}", max_age)?;
         }
         if let Some(domain) = &self.domain {
-            cookie.push_str(&format!("; Domain={}", domain));
+            write!(f, "; Domain={}", domain)?;
         }
         if let Some(path) = &self.path {
-            cookie.push_str(&format!("; Path={}", path));
+            write!(f, "; Path={}", path)?;
         }
         if let Some(same_site) = &self.same_site {
-            cookie.push_str(&format!("; SameSite={}", same_site));
+            write!(f, "; SameSite={}", same_site)?;
         }
-        if let Some(secure) = &self.secure {
-            if *secure {
-                cookie.push_str("; Secure");
-            }
+        if self.secure == Some(true) {
+            f.write_str("; Secure")?;
         }
-        if let Some(http_only) = &self.http_only {
-            if *http_only {
-                cookie.push_str("; HttpOnly");
-            }
+        if self.http_only == Some(true) {
+            f.write_str("; HttpOnly")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::StatusCode;
+    use chrono::TimeZone;
+
+    #[test]
+    fn parses_attributes() {
+        let cookie = Cookie::parse(
+            "id=a3fWa; Expires=Wed, 21 Oct 2015 07:28:00 GMT; Max-Age=2592000; \
+             Domain=.example.com; Path=/docs; SameSite=lax; Secure; HttpOnly; Priority=High",
+        )
+        .unwrap();
+        assert_eq!(cookie.name, "id");
+        assert_eq!(cookie.value, "a3fWa");
+        assert_eq!(
+            cookie.expires,
+            Some(Utc.with_ymd_and_hms(2015, 10, 21, 7, 28, 0).unwrap())
+        );
+        assert_eq!(cookie.max_age, Some(2592000));
+        assert_eq!(cookie.domain.as_deref(), Some("example.com"));
+        assert_eq!(cookie.path.as_deref(), Some("/docs"));
+        assert_eq!(cookie.same_site, Some(SameSite::Lax));
+        assert_eq!(cookie.secure, Some(true));
+        assert_eq!(cookie.http_only, Some(true));
+
+        let cookie = Cookie::parse("id=1; expires=Wed, 21-Oct-2015 07:28:00 GMT").unwrap();
+        assert!(cookie.expires.is_some());
+        let cookie = Cookie::parse("id=1; Max-Age=-1").unwrap();
+        assert!(cookie.is_removal());
+    }
+
+    #[test]
+    fn rejects_invalid_attributes() {
+        assert_eq!(Cookie::parse("no pair"), Err(CookieError::Malformed));
+        assert_eq!(
+            Cookie::parse("id=1; Expires=tomorrow"),
+            Err(CookieError::InvalidAttribute("Expires"))
+        );
+        assert_eq!(
+            Cookie::parse("id=1; Max-Age=forever"),
+            Err(CookieError::InvalidAttribute("Max-Age"))
+        );
+        assert_eq!(
+            Cookie::parse("id=1; SameSite=sometimes"),
+            Err(CookieError::InvalidAttribute("SameSite"))
+        );
+    }
+
+    #[test]
+    fn same_site_none_requires_secure() {
+        assert_eq!(
+            Cookie::parse("id=1; SameSite=None"),
+            Err(CookieError::SameSiteNoneWithoutSecure)
+        );
+        assert_eq!(
+            Cookie::parse("id=1; SameSite=None; Secure")
+                .unwrap()
+                .same_site,
+            Some(SameSite::None)
+        );
+        let cookie = Cookie::new("id", "1")
+            .with_same_site(SameSite::None)
+            .with_secure(false);
+        assert_eq!(
+            cookie.validate(),
+            Err(CookieError::SameSiteNoneWithoutSecure)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_names_and_attribute_values() {
+        for name in ["", "user id", "user;id", "user=id", "usér", "(id)"] {
+            assert_eq!(
+                Cookie::new(name, "1").validate(),
+                Err(CookieError::InvalidName(name.to_string())),
+                "{}",
+                name
+            );
+        }
+        assert!(Cookie::new("__Host-id!", "1").validate().is_ok());
+        assert_eq!(
+            Cookie::new("id", "1")
+                .with_path("/; Domain=evil.com")
+                .validate(),
+            Err(CookieError::InvalidAttribute("Path"))
+        );
+        assert_eq!(
+            Cookie::new("id", "1")
+                .with_domain("example.com\r\nSet-Cookie: x=1")
+                .validate(),
+            Err(CookieError::InvalidAttribute("Domain"))
+        );
+    }
+
+    #[test]
+    fn percent_encoding_round_trips() {
+        for value in ["a b; c", "100%", "\"quoted\"", "emoji 🍪", "a,b\\c", ""] {
+            let cookie = Cookie::new("id", value).with_path("/");
+            let set_cookie = cookie.to_string();
+            assert!(!set_cookie[..set_cookie.find("; Path").unwrap()].contains([' ', ';']));
+            assert_eq!(Cookie::parse(&set_cookie).unwrap(), cookie, "{}", value);
+            let header = set_cookie.split(';').next().unwrap();
+            assert_eq!(parse_cookie_header(header)[0].value, value);
         }
-        cookie
+        // Values the client quoted itself
+        assert_eq!(Cookie::parse("id=\"a%20b\"").unwrap().value, "a b");
+    }
+
+    #[test]
+    fn formats_set_cookie() {
+        let cookie = Cookie::new("id", "1")
+            .with_expires(Utc.with_ymd_and_hms(2015, 10, 21, 7, 28, 0).unwrap())
+            .with_max_age(60)
+            .with_domain("example.com")
+            .with_path("/")
+            .with_same_site(SameSite::Strict)
+            .with_secure(true)
+            .with_http_only(true);
+        assert_eq!(
+            cookie.to_string(),
+            "id=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT; Max-Age=60; Domain=example.com; \
+             Path=/; SameSite=Strict; Secure; HttpOnly"
+        );
+        assert_eq!(
+            Cookie::new("id", "1").with_secure(false).to_string(),
+            "id=1"
+        );
+    }
+
+    #[test]
+    fn add_cookie_returns_the_error() {
+        let mut response = Response::new(StatusCode::OK);
+        assert_eq!(
+            response.add_cookie(Cookie::new("user id", "1")),
+            Err(CookieError::InvalidName("user id".to_string()))
+        );
+        assert!(response.cookies.is_empty());
+
+        response.add_cookie(Cookie::new("id", "1")).unwrap();
+        response.add_cookie(Cookie::new("id", "2")).unwrap();
+        response
+            .add_cookie(Cookie::new("id", "3").with_path("/admin"))
+            .unwrap();
+        let values: Vec<&str> = response.cookies.iter().map(|c| c.value.as_str()).collect();
+        assert_eq!(values, ["2", "3"]);
+
+        // The builder logs invalid cookies and leaves them out
+        let response = Response::new(StatusCode::OK)
+            .with_cookie(Cookie::new("id", "1").with_same_site(SameSite::None));
+        assert!(response.cookies.is_empty());
     }
 }
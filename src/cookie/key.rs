@@ -0,0 +1,152 @@
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use std::fmt;
+
+// Secrets shorter than this are too easy to guess
+pub const MIN_SECRET_LENGTH: usize = 32;
+
+// Secret used for signed and private cookies. Separate keys for signing and encryption
+// are derived from a single secret, so the same secret can safely be used for both.
+#[derive(Clone)]
+pub struct Key {
+    signing: [u8; 32],
+    encryption: [u8; 32],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyTooShort;
+
+impl fmt::Display for KeyTooShort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "keys have to be at least {} bytes long",
+            MIN_SECRET_LENGTH
+        )
+    }
+}
+
+impl std::error::Error for KeyTooShort {}
+
+impl Key {
+    pub fn derive(secret: &[u8]) -> Result<Key, KeyTooShort> {
+        if secret.len() < MIN_SECRET_LENGTH {
+            return Err(KeyTooShort);
+        }
+        let derive = |purpose: &[u8]| -> [u8; 32] {
+            let mut mac =
+                Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+            mac.update(purpose);
+            mac.finalize().into_bytes().into()
+        };
+        Ok(Key {
+            signing: derive(b"surfer cookie signing"),
+            encryption: derive(b"surfer cookie encryption"),
+        })
+    }
+
+    // Random key, cookies signed or encrypted with it become invalid on restart
+    pub fn generate() -> Key {
+        let mut secret = [0; 64];
+        rand::thread_rng().fill_bytes(&mut secret);
+        Key::derive(&secret).expect("generated secret is long enough")
+    }
+
+    pub(crate) fn signing(&self) -> &[u8; 32] {
+        &self.signing
+    }
+
+    pub(crate) fn encryption(&self) -> &[u8; 32] {
+        &self.encryption
+    }
+}
+
+// Never print key material
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+// The current key plus older ones. New cookies are always signed and encrypted with the
+// current key, while cookies issued with a previous key are still accepted on read.
+#[derive(Debug, Clone)]
+pub struct KeyRing {
+    current: Key,
+    previous: Vec<Key>,
+}
+
+impl KeyRing {
+    pub fn new(current: Key) -> KeyRing {
+        KeyRing {
+            current,
+            previous: Vec::new(),
+        }
+    }
+
+    pub fn with_previous(mut self, key: Key) -> KeyRing {
+        self.previous.push(key);
+        self
+    }
+
+    pub fn current(&self) -> &Key {
+        &self.current
+    }
+
+    // Current key first
+    pub fn keys(&self) -> impl Iterator<Item = &Key> {
+        std::iter::once(&self.current).chain(self.previous.iter())
+    }
+}
+
+impl From<Key> for KeyRing {
+    fn from(key: Key) -> Self {
+        KeyRing::new(key)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // Key derived from a secret of 32 times `byte`, for the jar tests as well
+    pub(crate) fn key(byte: u8) -> Key {
+        Key::derive(&[byte; 32]).unwrap()
+    }
+
+    #[test]
+    fn derive_rejects_short_secrets() {
+        let error = Key::derive(&[1; MIN_SECRET_LENGTH - 1]).unwrap_err();
+        assert_eq!(error, KeyTooShort);
+        assert_eq!(error.to_string(), "keys have to be at least 32 bytes long");
+        assert!(Key::derive(b"").is_err());
+        assert!(Key::derive(&[1; MIN_SECRET_LENGTH]).is_ok());
+    }
+
+    #[test]
+    fn derives_separate_keys_per_purpose() {
+        let first = key(1);
+        assert_ne!(first.signing(), first.encryption());
+        assert_eq!(first.signing(), key(1).signing());
+        assert_ne!(first.signing(), key(2).signing());
+        assert_ne!(Key::generate().signing(), Key::generate().signing());
+    }
+
+    #[test]
+    fn debug_hides_key_material() {
+        assert_eq!(format!("{:?}", key(1)), "Key(..)");
+    }
+
+    #[test]
+    fn key_ring_lists_current_key_first() {
+        let ring = KeyRing::new(key(3))
+            .with_previous(key(2))
+            .with_previous(key(1));
+        let signing: Vec<_> = ring.keys().map(|key| *key.signing()).collect();
+        assert_eq!(
+            signing,
+            [*key(3).signing(), *key(2).signing(), *key(1).signing()]
+        );
+    }
+}
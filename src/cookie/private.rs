@@ -0,0 +1,152 @@
+use crate::cookie::key::{Key, KeyRing};
+use crate::cookie::{Cookie, CookieJar};
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::RngCore;
+
+const NONCE_LENGTH: usize = 12;
+
+// View of a `CookieJar` that encrypts added cookies with AES-256-GCM and decrypts them on read.
+// Private values can neither be read nor changed by the client.
+pub struct PrivateJar<'a> {
+    jar: &'a mut CookieJar,
+    keys: &'a KeyRing,
+}
+
+impl<'a> PrivateJar<'a> {
+    pub(crate) fn new(jar: &'a mut CookieJar, keys: &'a KeyRing) -> PrivateJar<'a> {
+        PrivateJar { jar, keys }
+    }
+
+    // The cookie with its decrypted value, `None` if it's missing or has been tampered with
+    pub fn get(&self, name: &str) -> Option<Cookie> {
+        decrypt(self.keys, self.jar.get(name)?)
+    }
+
+    pub fn add(&mut self, cookie: Cookie) {
+        self.jar.add(encrypt(self.keys.current(), cookie));
+    }
+
+    pub fn remove(&mut self, cookie: Cookie) {
+        self.jar.remove(cookie);
+    }
+}
+
+// Replaces the value with the base64 encoded nonce and ciphertext
+pub fn encrypt(key: &Key, mut cookie: Cookie) -> Cookie {
+    let mut nonce = [0; NONCE_LENGTH];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let cipher = Aes256Gcm::new(key.encryption().into());
+    // The name is authenticated as well, so a value can't be moved to another cookie
+    let payload = Payload {
+        msg: cookie.value.as_bytes(),
+        aad: cookie.name.as_bytes(),
+    };
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), payload)
+        .expect("cookie values are short enough to encrypt");
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    cookie.value = URL_SAFE_NO_PAD.encode(sealed);
+    cookie
+}
+
+// Tries every key, `None` if none of them decrypts the value
+pub fn decrypt(keys: &KeyRing, cookie: &Cookie) -> Option<Cookie> {
+    let sealed = URL_SAFE_NO_PAD.decode(&cookie.value).ok()?;
+    if sealed.len() < NONCE_LENGTH {
+        return None;
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+    let plaintext = keys.keys().find_map(|key| {
+        let payload = Payload {
+            msg: ciphertext,
+            aad: cookie.name.as_bytes(),
+        };
+        Aes256Gcm::new(key.encryption().into())
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .ok()
+    })?;
+    Some(Cookie {
+        value: String::from_utf8(plaintext).ok()?,
+        ..cookie.clone()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cookie::key::tests::key;
+
+    #[test]
+    fn value_is_hidden_from_the_client() {
+        let keys = KeyRing::new(key(1));
+        let encrypted = encrypt(keys.current(), Cookie::new("flash", "saved"));
+        assert!(!encrypted.value.contains("saved"));
+        assert!(
+            !String::from_utf8_lossy(&URL_SAFE_NO_PAD.decode(&encrypted.value).unwrap())
+                .contains("saved")
+        );
+        assert_eq!(decrypt(&keys, &encrypted).unwrap().value, "saved");
+    }
+
+    #[test]
+    fn uses_a_fresh_nonce_per_cookie() {
+        let keys = KeyRing::new(key(1));
+        let first = encrypt(keys.current(), Cookie::new("flash", "saved"));
+        let second = encrypt(keys.current(), Cookie::new("flash", "saved"));
+        // Reusing a nonce with AES-GCM would leak the XOR of both values
+        let nonce = |cookie: &Cookie| {
+            URL_SAFE_NO_PAD.decode(&cookie.value).unwrap()[..NONCE_LENGTH].to_vec()
+        };
+        assert_ne!(nonce(&first), nonce(&second));
+        assert_ne!(first.value, second.value);
+        assert_eq!(decrypt(&keys, &second).unwrap().value, "saved");
+    }
+
+    #[test]
+    fn rejects_flipped_ciphertext_bits() {
+        let keys = KeyRing::new(key(1));
+        let encrypted = encrypt(keys.current(), Cookie::new("flash", "saved"));
+        let sealed = URL_SAFE_NO_PAD.decode(&encrypted.value).unwrap();
+        for index in [0, NONCE_LENGTH, sealed.len() - 1] {
+            let mut tampered = sealed.clone();
+            tampered[index] ^= 1;
+            let tampered = Cookie::new("flash", &URL_SAFE_NO_PAD.encode(tampered));
+            assert!(decrypt(&keys, &tampered).is_none());
+        }
+        assert!(decrypt(&keys, &Cookie::new("flash", "saved")).is_none());
+        assert!(decrypt(&keys, &Cookie::new("flash", "")).is_none());
+    }
+
+    #[test]
+    fn name_is_authenticated() {
+        let keys = KeyRing::new(key(1));
+        let encrypted = encrypt(keys.current(), Cookie::new("flash", "saved"));
+        let renamed = Cookie::new("user", &encrypted.value);
+        assert!(decrypt(&keys, &renamed).is_none());
+    }
+
+    #[test]
+    fn decrypts_with_previous_keys_only_while_listed() {
+        let encrypted = encrypt(&key(1), Cookie::new("flash", "saved"));
+        let rotated = KeyRing::new(key(2)).with_previous(key(1));
+        assert_eq!(decrypt(&rotated, &encrypted).unwrap().value, "saved");
+        assert!(decrypt(&KeyRing::new(key(2)), &encrypted).is_none());
+    }
+
+    #[test]
+    fn jar_encrypts_with_the_current_key() {
+        let keys = KeyRing::new(key(2)).with_previous(key(1));
+        let mut jar = CookieJar::new();
+        jar.private(&keys).add(Cookie::new("flash", "saved"));
+        assert_ne!(jar.get("flash").unwrap().value, "saved");
+        assert_eq!(jar.private(&keys).get("flash").unwrap().value, "saved");
+        // Readable without the old key
+        let current = KeyRing::new(key(2));
+        assert_eq!(jar.private(&current).get("flash").unwrap().value, "saved");
+    }
+}
@@ -0,0 +1,141 @@
+use crate::cookie::key::{Key, KeyRing};
+use crate::cookie::{Cookie, CookieJar};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+// Length of a base64 encoded HMAC-SHA256 tag without padding
+const TAG_LENGTH: usize = 43;
+
+// View of a `CookieJar` that signs added cookies and only returns cookies with a valid
+// signature. Signed values can be read by the client, but not changed.
+pub struct SignedJar<'a> {
+    jar: &'a mut CookieJar,
+    keys: &'a KeyRing,
+}
+
+impl<'a> SignedJar<'a> {
+    pub(crate) fn new(jar: &'a mut CookieJar, keys: &'a KeyRing) -> SignedJar<'a> {
+        SignedJar { jar, keys }
+    }
+
+    // The cookie with its original value, `None` if it's missing or has been tampered with
+    pub fn get(&self, name: &str) -> Option<Cookie> {
+        verify(self.keys, self.jar.get(name)?)
+    }
+
+    pub fn add(&mut self, cookie: Cookie) {
+        self.jar.add(sign(self.keys.current(), cookie));
+    }
+
+    pub fn remove(&mut self, cookie: Cookie) {
+        self.jar.remove(cookie);
+    }
+}
+
+fn tag(key: &Key, name: &str, value: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.signing()).expect("HMAC accepts keys of any length");
+    // The name is part of the signature, so a value can't be moved to another cookie
+    mac.update(name.as_bytes());
+    mac.update(b"=");
+    mac.update(value.as_bytes());
+    mac
+}
+
+// Prepends the signature of name and value to the value
+pub fn sign(key: &Key, mut cookie: Cookie) -> Cookie {
+    let tag = tag(key, &cookie.name, &cookie.value)
+        .finalize()
+        .into_bytes();
+    cookie.value = format!("{}{}", URL_SAFE_NO_PAD.encode(tag), cookie.value);
+    cookie
+}
+
+// Checks the signature against every key and strips it from the value
+pub fn verify(keys: &KeyRing, cookie: &Cookie) -> Option<Cookie> {
+    if !cookie.value.is_char_boundary(TAG_LENGTH) {
+        return None;
+    }
+    let (encoded_tag, value) = cookie.value.split_at(TAG_LENGTH);
+    let expected = URL_SAFE_NO_PAD.decode(encoded_tag).ok()?;
+    let valid = keys.keys().any(|key| {
+        // `verify_slice` compares in constant time
+        tag(key, &cookie.name, value)
+            .verify_slice(&expected)
+            .is_ok()
+    });
+    valid.then(|| Cookie {
+        value: value.to_string(),
+        ..cookie.clone()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cookie::key::tests::key;
+
+    #[test]
+    fn signed_value_stays_readable() {
+        let keys = KeyRing::new(key(1));
+        let signed = sign(keys.current(), Cookie::new("user", "42"));
+        // Signing only protects the value, the client still sees it
+        assert_eq!(signed.value.len(), TAG_LENGTH + 2);
+        assert!(signed.value.ends_with("42"));
+        assert_eq!(verify(&keys, &signed).unwrap().value, "42");
+    }
+
+    #[test]
+    fn rejects_changed_value() {
+        let keys = KeyRing::new(key(1));
+        let mut signed = sign(keys.current(), Cookie::new("user", "42"));
+        signed.value.replace_range(TAG_LENGTH.., "1");
+        assert!(verify(&keys, &signed).is_none());
+    }
+
+    #[test]
+    fn rejects_forged_or_missing_tag() {
+        let keys = KeyRing::new(key(1));
+        let mut signed = sign(keys.current(), Cookie::new("user", "42"));
+        let first = if signed.value.starts_with('A') {
+            "B"
+        } else {
+            "A"
+        };
+        signed.value.replace_range(..1, first);
+        assert!(verify(&keys, &signed).is_none());
+        assert!(verify(&keys, &Cookie::new("user", "42")).is_none());
+        assert!(verify(&keys, &Cookie::new("user", "ä".repeat(30).as_str())).is_none());
+    }
+
+    #[test]
+    fn signature_covers_the_name() {
+        let keys = KeyRing::new(key(1));
+        let signed = sign(keys.current(), Cookie::new("user", "42"));
+        let renamed = Cookie::new("admin", &signed.value);
+        assert!(verify(&keys, &renamed).is_none());
+    }
+
+    #[test]
+    fn accepts_previous_keys_until_retired() {
+        let signed = sign(&key(1), Cookie::new("user", "42"));
+        let rotated = KeyRing::new(key(2)).with_previous(key(1));
+        assert_eq!(verify(&rotated, &signed).unwrap().value, "42");
+        let retired = KeyRing::new(key(2));
+        assert!(verify(&retired, &signed).is_none());
+    }
+
+    #[test]
+    fn jar_hides_tampered_cookies() {
+        let keys = KeyRing::new(key(1));
+        let mut jar = CookieJar::new();
+        jar.signed(&keys).add(Cookie::new("user", "42"));
+        assert_eq!(jar.signed(&keys).get("user").unwrap().value, "42");
+
+        let forged = jar.get("user").unwrap().value.replace("42", "43");
+        jar.add(Cookie::new("user", &forged));
+        assert!(jar.signed(&keys).get("user").is_none());
+    }
+}
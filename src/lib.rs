@@ -1,7 +1,13 @@
+pub mod auth;
 pub mod cookie;
+pub mod encoding;
 mod logs;
+pub mod middleware;
 pub mod request;
 pub mod response;
 pub mod server;
+pub mod session;
+pub mod template;
 mod utils;
+pub mod websocket;
 pub use surfer_macros;
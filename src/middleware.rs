@@ -0,0 +1,152 @@
+pub mod compression;
+pub mod cors;
+pub mod csrf;
+pub mod rate_limit;
+pub mod security_headers;
+
+use crate::request::{Method, Request};
+use crate::response::Response;
+use crate::server::AsyncHandler;
+use futures::future::LocalBoxFuture;
+use std::collections::HashMap;
+use std::future::Future;
+use std::rc::Rc;
+
+// Code that runs around handlers. A middleware gets the request before the handler and
+// decides what to do with it: change it and pass it on with `next.run(request)`,
+// post-process the response that comes back, or answer on its own without calling `next`.
+//
+//     struct Timing;
+//
+//     impl Middleware for Timing {
+//         async fn handle(&self, request: Request, next: Next<'_>) -> Response {
+//             let start = Instant::now();
+//             let mut response = next.run(request).await;
+//             response.set_header("Server-Timing", format!("total;dur={}", start.elapsed().as_millis()));
+//             response
+//         }
+//     }
+//
+// Middleware runs in the order it was added: server-wide middleware first, then the
+// middleware of the route's group, then the route's own. Responses pass through in reverse.
+pub trait Middleware {
+    fn handle(&self, request: Request, next: Next<'_>) -> impl Future<Output = Response>;
+}
+
+// Object safe version of `Middleware`, so different middleware can live in one stack
+trait DynMiddleware {
+    fn handle_boxed<'a>(&'a self, request: Request, next: Next<'a>)
+        -> LocalBoxFuture<'a, Response>;
+}
+
+impl<M: Middleware> DynMiddleware for M {
+    fn handle_boxed<'a>(
+        &'a self,
+        request: Request,
+        next: Next<'a>,
+    ) -> LocalBoxFuture<'a, Response> {
+        Box::pin(self.handle(request, next))
+    }
+}
+
+type Endpoint<'a> = dyn Fn(Request) -> LocalBoxFuture<'a, Response> + 'a;
+
+// The rest of the chain after the current middleware
+pub struct Next<'a> {
+    middleware: &'a [Rc<dyn DynMiddleware>],
+    endpoint: &'a Endpoint<'a>,
+}
+
+impl Next<'_> {
+    pub async fn run(self, request: Request) -> Response {
+        match self.middleware.split_first() {
+            Some((middleware, rest)) => {
+                let next = Next {
+                    middleware: rest,
+                    endpoint: self.endpoint,
+                };
+                middleware.handle_boxed(request, next).await
+            }
+            None => (self.endpoint)(request).await,
+        }
+    }
+}
+
+// Ordered list of middleware, the first one added runs first
+#[derive(Clone, Default)]
+pub struct MiddlewareStack {
+    middleware: Vec<Rc<dyn DynMiddleware>>,
+}
+
+impl MiddlewareStack {
+    pub fn new() -> MiddlewareStack {
+        MiddlewareStack::default()
+    }
+
+    pub fn with(mut self, middleware: impl Middleware + 'static) -> MiddlewareStack {
+        self.push(middleware);
+        self
+    }
+
+    pub fn push(&mut self, middleware: impl Middleware + 'static) {
+        self.middleware.push(Rc::new(middleware));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.middleware.is_empty()
+    }
+
+    // Appends the middleware of `other`, which then runs after this stack's own
+    pub fn extend(&mut self, other: &MiddlewareStack) {
+        self.middleware.extend(other.middleware.iter().cloned());
+    }
+
+    pub async fn run<'a>(
+        &'a self,
+        request: Request,
+        endpoint: &'a (dyn Fn(Request) -> LocalBoxFuture<'a, Response> + 'a),
+    ) -> Response {
+        Next {
+            middleware: &self.middleware,
+            endpoint,
+        }
+        .run(request)
+        .await
+    }
+
+    // Handler that runs this stack before `handler`
+    pub fn wrap(&self, handler: AsyncHandler) -> AsyncHandler {
+        if self.is_empty() {
+            return handler;
+        }
+        let stack = self.clone();
+        let handler = Rc::new(handler);
+        Box::new(move |request: Request| {
+            let stack = stack.clone();
+            let handler = handler.clone();
+            Box::pin(async move {
+                let endpoint =
+                    |request: Request| -> LocalBoxFuture<'_, Response> { handler(request) };
+                stack.run(request, &endpoint).await
+            })
+        })
+    }
+}
+
+// Entry of `routes`, keyed `"METHOD path"` like the server's routes, that applies to the request.
+// HEAD requests fall back to the GET entry the same way they fall back to the GET route,
+// so they can't be used to get around settings made for GET.
+pub(crate) fn route_entry<'a, T>(
+    routes: &'a HashMap<String, T>,
+    request: &Request,
+) -> Option<(&'a str, &'a T)> {
+    let path = request.path.split('?').next().unwrap_or_default();
+    let entry = routes.get_key_value(&format!("{} {}", request.method, path));
+    let entry = match entry {
+        None if request.method == Method::HEAD.as_str() => {
+            routes.get_key_value(&format!("{} {}", Method::GET, path))
+        }
+        entry => entry,
+    };
+    entry.map(|(route, value)| (route.as_str(), value))
+}
@@ -0,0 +1,388 @@
+use crate::middleware::{Middleware, Next};
+use crate::request::accept::{best_match, AcceptEncoding};
+use crate::request::Request;
+use crate::response::{Body, Response, StatusCode};
+use crate::utils::is_compressible;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use futures::stream::{self, StreamExt};
+use std::io::{self, Write};
+
+pub use crate::encoding::Encoding;
+
+// Bodies smaller than this barely shrink, if at all
+pub const DEFAULT_MIN_SIZE: u64 = 1024;
+
+enum Encoder {
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(encoding: Encoding, level: u32) -> Encoder {
+        match encoding {
+            Encoding::Brotli => Encoder::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                4096,
+                // Brotli levels go up to 11, the 0-9 scale of gzip is mapped onto it
+                (level * 11).div_ceil(9),
+                22,
+            ))),
+            Encoding::Gzip => {
+                Encoder::Gzip(GzEncoder::new(Vec::new(), flate2::Compression::new(level)))
+            }
+            // `deflate` in HTTP means the zlib format (RFC 9110, 8.4.1.2)
+            Encoding::Deflate => Encoder::Deflate(ZlibEncoder::new(
+                Vec::new(),
+                flate2::Compression::new(level),
+            )),
+        }
+    }
+
+    // Compresses `data` and returns everything that can be sent so far.
+    // Flushing after every chunk keeps streamed responses like events from being held back.
+    fn write(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Brotli(encoder) => {
+                encoder.write_all(data)?;
+                encoder.flush()?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+            Encoder::Gzip(encoder) => {
+                encoder.write_all(data)?;
+                encoder.flush()?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+            Encoder::Deflate(encoder) => {
+                encoder.write_all(data)?;
+                encoder.flush()?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+        }
+    }
+
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Brotli(encoder) => Ok(encoder.into_inner()),
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Deflate(encoder) => encoder.finish(),
+        }
+    }
+
+    fn compress(mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut compressed = self.write(data)?;
+        compressed.extend(self.finish()?);
+        Ok(compressed)
+    }
+}
+
+// Compresses text responses with the best encoding the client accepts.
+// Bodies that are already in memory are compressed at once, streamed bodies chunk by chunk.
+pub struct Compression {
+    // In order of preference if the client accepts several equally
+    encodings: Vec<Encoding>,
+    min_size: u64,
+    level: u32,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::new()
+    }
+}
+
+impl Compression {
+    pub fn new() -> Compression {
+        Compression {
+            encodings: vec![Encoding::Brotli, Encoding::Gzip, Encoding::Deflate],
+            min_size: DEFAULT_MIN_SIZE,
+            level: 6,
+        }
+    }
+
+    pub fn with_encodings(mut self, encodings: &[Encoding]) -> Compression {
+        self.encodings = encodings.to_vec();
+        self
+    }
+
+    // Bodies with a known length below this are sent as they are
+    pub fn with_min_size(mut self, min_size: u64) -> Compression {
+        self.min_size = min_size;
+        self
+    }
+
+    // From 0 (fastest) to 9 (smallest)
+    pub fn with_level(mut self, level: u32) -> Compression {
+        self.level = level.min(9);
+        self
+    }
+
+    fn is_eligible(&self, response: &Response) -> bool {
+        let compressible_type = response.header("Content-Type").is_some_and(|content_type| {
+            // Events have to reach the client right away and are tiny anyway
+            is_compressible(content_type) && !content_type.starts_with("text/event-stream")
+        });
+        let no_transform = response
+            .header("Cache-Control")
+            .is_some_and(|cache_control| {
+                cache_control
+                    .split(',')
+                    .any(|directive| directive.trim().eq_ignore_ascii_case("no-transform"))
+            });
+        compressible_type
+            && !no_transform
+            && response.allows_body()
+            && response.status_code != StatusCode::PARTIAL_CONTENT
+            && response.header("Content-Encoding").is_none()
+            && response.header("Content-Range").is_none()
+            && response
+                .body
+                .len()
+                .is_none_or(|length| length >= self.min_size)
+    }
+}
+
+impl Middleware for Compression {
+    async fn handle(&self, request: Request, next: Next<'_>) -> Response {
+        let accept_encoding = AcceptEncoding::parse(request.header("Accept-Encoding"));
+        let mut response = next.run(request).await;
+        if !self.is_eligible(&response) {
+            return response;
+        }
+        // The body depends on Accept-Encoding from here on, whether it's compressed or not
+        response.add_vary("Accept-Encoding");
+        // Without the header clients most likely can't handle any encoding
+        if !accept_encoding.is_present() {
+            return response;
+        }
+        let Some(encoding) = best_match(self.encodings.iter().copied(), |encoding| {
+            accept_encoding.quality(encoding.as_str())
+        }) else {
+            return response;
+        };
+
+        let encoder = Encoder::new(encoding, self.level);
+        match std::mem::take(&mut response.body) {
+            Body::Bytes(bytes) => match encoder.compress(&bytes) {
+                // Not worth it if it doesn't get any smaller
+                Ok(compressed) if compressed.len() < bytes.len() => {
+                    response.body = Body::Bytes(compressed);
+                }
+                _ => {
+                    response.body = Body::Bytes(bytes);
+                    return response;
+                }
+            },
+            body => {
+                let chunks = body.into_stream();
+                let compressed = stream::unfold(Some((chunks, encoder)), |state| async move {
+                    let (mut chunks, mut encoder) = state?;
+                    loop {
+                        match chunks.next().await {
+                            Some(Ok(chunk)) => match encoder.write(&chunk) {
+                                Ok(compressed) if compressed.is_empty() => continue,
+                                Ok(compressed) => {
+                                    return Some((Ok(compressed), Some((chunks, encoder))))
+                                }
+                                Err(err) => return Some((Err(err), None)),
+                            },
+                            Some(Err(err)) => return Some((Err(err), None)),
+                            None => return Some((encoder.finish(), None)),
+                        }
+                    }
+                });
+                response.body = Body::from_stream(compressed);
+            }
+        }
+        response.set_header("Content-Encoding", encoding.as_str());
+        // Validators of the uncompressed body don't apply to the compressed one
+        if let Some(etag) = response.header("ETag").map(str::to_string) {
+            if !etag.starts_with("W/") {
+                response.set_header("ETag", format!("W/{}", etag));
+            }
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::MiddlewareStack;
+    use futures::future::LocalBoxFuture;
+    use std::io::Read;
+
+    fn text() -> String {
+        "hello world ".repeat(200)
+    }
+
+    async fn run(
+        compression: Compression,
+        accept_encoding: Option<&str>,
+        response: fn() -> Response,
+    ) -> Response {
+        let stack = MiddlewareStack::new().with(compression);
+        let endpoint = move |_: Request| -> LocalBoxFuture<'_, Response> {
+            Box::pin(async move { response() })
+        };
+        let mut request = Request::new();
+        request.method = "GET".to_string();
+        request.path = "/".to_string();
+        if let Some(accept_encoding) = accept_encoding {
+            request
+                .headers
+                .insert("Accept-Encoding".to_string(), accept_encoding.to_string());
+        }
+        stack.run(request, &endpoint).await
+    }
+
+    fn decode(encoding: &str, data: &[u8]) -> String {
+        let mut decoded = String::new();
+        match encoding {
+            "br" => brotli::Decompressor::new(data, 4096).read_to_string(&mut decoded),
+            "gzip" => flate2::read::GzDecoder::new(data).read_to_string(&mut decoded),
+            "deflate" => flate2::read::ZlibDecoder::new(data).read_to_string(&mut decoded),
+            _ => panic!("unexpected encoding {}", encoding),
+        }
+        .unwrap();
+        decoded
+    }
+
+    #[async_std::test]
+    async fn picks_encoding_by_quality_then_preference() {
+        let cases = [
+            ("gzip;q=0.5, br;q=0.9", Some("br")),
+            ("gzip, deflate;q=0.5, br;q=0", Some("gzip")),
+            ("deflate", Some("deflate")),
+            ("gzip, br", Some("br")),
+            ("*", Some("br")),
+            ("identity", None),
+            ("gzip;q=0, compress", None),
+        ];
+        for (accept_encoding, expected) in cases {
+            let response = run(Compression::new(), Some(accept_encoding), || {
+                Response::text(text())
+            })
+            .await;
+            assert_eq!(
+                response.header("Content-Encoding"),
+                expected,
+                "{}",
+                accept_encoding
+            );
+            assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+            if let Some(encoding) = expected {
+                assert_eq!(decode(encoding, response.body.as_bytes().unwrap()), text());
+            }
+        }
+
+        let gzip_only = Compression::new().with_encodings(&[Encoding::Gzip]);
+        let response = run(gzip_only, Some("br, gzip;q=0.1"), || Response::text(text())).await;
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+    }
+
+    #[async_std::test]
+    async fn missing_accept_encoding_still_varies() {
+        let response = run(Compression::new(), None, || Response::text(text())).await;
+        assert_eq!(response.header("Content-Encoding"), None);
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.body.as_bytes(), Some(text().as_bytes()));
+    }
+
+    #[async_std::test]
+    async fn skips_ineligible_responses() {
+        let responses: [fn() -> Response; 6] = [
+            || Response::text("too small"),
+            || {
+                let mut response = Response::text(text());
+                response.set_header("Cache-Control", "public, No-Transform");
+                response
+            },
+            || {
+                let mut response = Response::text(text());
+                response.set_header("Content-Encoding", "gzip");
+                response
+            },
+            || {
+                let mut response = Response::text(text());
+                response.status_code = StatusCode::PARTIAL_CONTENT;
+                response.set_header("Content-Range", "bytes 0-2399/4800");
+                response
+            },
+            || {
+                let mut response = Response::text(text());
+                response.set_header("Content-Type", "text/event-stream");
+                response
+            },
+            || {
+                let mut response = Response::text(text());
+                response.set_header("Content-Type", "image/png");
+                response
+            },
+        ];
+        for (index, response) in responses.into_iter().enumerate() {
+            let original = response();
+            let response = run(Compression::new(), Some("gzip"), response).await;
+            assert_eq!(
+                response.header("Content-Encoding"),
+                original.header("Content-Encoding"),
+                "response {}",
+                index
+            );
+            assert_eq!(response.header("Vary"), None, "response {}", index);
+            assert_eq!(response.body.as_bytes(), original.body.as_bytes());
+        }
+    }
+
+    #[async_std::test]
+    async fn keeps_bodies_that_dont_shrink() {
+        let compression = Compression::new().with_min_size(0);
+        let response = run(compression, Some("gzip"), || Response::text("a")).await;
+        assert_eq!(response.header("Content-Encoding"), None);
+        assert_eq!(response.body.as_bytes(), Some(&b"a"[..]));
+    }
+
+    #[async_std::test]
+    async fn weakens_strong_etags() {
+        let response = run(Compression::new(), Some("gzip"), || {
+            let mut response = Response::text(text());
+            response.set_header("ETag", "\"abc\"");
+            response
+        })
+        .await;
+        assert_eq!(response.header("ETag"), Some("W/\"abc\""));
+
+        let response = run(Compression::new(), Some("gzip"), || {
+            let mut response = Response::text(text());
+            response.set_header("ETag", "W/\"abc\"");
+            response.set_header("Vary", "Cookie");
+            response
+        })
+        .await;
+        assert_eq!(response.header("ETag"), Some("W/\"abc\""));
+        assert_eq!(response.header("Vary"), Some("Cookie, Accept-Encoding"));
+    }
+
+    #[async_std::test]
+    async fn compresses_streamed_bodies_chunk_by_chunk() {
+        for encoding in ["br", "gzip", "deflate"] {
+            let response = run(Compression::new(), Some(encoding), || {
+                let chunks = (0..10).map(|_| Ok("hello world ".repeat(20).into_bytes()));
+                Response::text("").with_body(Body::from_stream(stream::iter(chunks)))
+            })
+            .await;
+            assert_eq!(response.header("Content-Encoding"), Some(encoding));
+            assert_eq!(response.body.len(), None);
+
+            let chunks: Vec<Vec<u8>> = response
+                .body
+                .into_stream()
+                .map(|chunk| chunk.unwrap())
+                .collect()
+                .await;
+            // Every chunk is flushed on its own instead of waiting for the end
+            assert!(chunks.len() > 1, "{}", encoding);
+            assert_eq!(decode(encoding, &chunks.concat()), text());
+        }
+    }
+}
@@ -0,0 +1,422 @@
+use crate::middleware::{Middleware, Next};
+use crate::request::{Method, Request};
+use crate::response::{Response, StatusCode};
+use std::time::Duration;
+
+const DEFAULT_METHODS: [&str; 6] = ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE"];
+
+enum AllowOrigin {
+    Any,
+    List(Vec<String>),
+    Predicate(Box<dyn Fn(&str) -> bool>),
+}
+
+// Cross-origin resource sharing. Preflight requests are answered right away without
+// reaching any route, other requests get the CORS headers added to their response.
+// Nothing is allowed until origins are configured:
+//
+//     server.add_middleware(
+//         Cors::new()
+//             .with_origin("https://app.example.com")
+//             .with_credentials(true)
+//             .with_max_age(Duration::from_secs(600)),
+//     );
+pub struct Cors {
+    allow_origin: AllowOrigin,
+    allow_methods: Vec<String>,
+    // `None` allows whatever headers the preflight asks for
+    allow_headers: Option<Vec<String>>,
+    allow_credentials: bool,
+    max_age: Option<Duration>,
+    expose_headers: Vec<String>,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Cors::new()
+    }
+}
+
+impl Cors {
+    pub fn new() -> Cors {
+        Cors {
+            allow_origin: AllowOrigin::List(Vec::new()),
+            allow_methods: DEFAULT_METHODS.iter().map(|m| m.to_string()).collect(),
+            allow_headers: Some(Vec::new()),
+            allow_credentials: false,
+            max_age: None,
+            expose_headers: Vec::new(),
+        }
+    }
+
+    // Any origin with any headers, without credentials. Handy during development.
+    pub fn permissive() -> Cors {
+        Cors::new().with_any_origin().with_any_headers()
+    }
+
+    // Panics together with credentials, see `with_credentials`
+    pub fn with_any_origin(mut self) -> Cors {
+        self.allow_origin = AllowOrigin::Any;
+        self.assert_no_any_origin_with_credentials();
+        self
+    }
+
+    // Origins have to match exactly, e.g. `https://example.com:8443` without a trailing slash
+    pub fn with_origin(self, origin: &str) -> Cors {
+        self.with_origins(&[origin])
+    }
+
+    pub fn with_origins(mut self, origins: &[&str]) -> Cors {
+        let mut list = match self.allow_origin {
+            AllowOrigin::List(list) => list,
+            _ => Vec::new(),
+        };
+        list.extend(origins.iter().map(|origin| origin.to_string()));
+        self.allow_origin = AllowOrigin::List(list);
+        self
+    }
+
+    // Decides per origin, replaces any origins configured before
+    pub fn with_origin_fn<F>(mut self, predicate: F) -> Cors
+    where
+        F: Fn(&str) -> bool + 'static,
+    {
+        self.allow_origin = AllowOrigin::Predicate(Box::new(predicate));
+        self
+    }
+
+    pub fn with_methods(mut self, methods: &[Method]) -> Cors {
+        self.allow_methods = methods.iter().map(|m| m.to_string()).collect();
+        self
+    }
+
+    pub fn with_headers(mut self, headers: &[&str]) -> Cors {
+        self.allow_headers = Some(headers.iter().map(|h| h.to_string()).collect());
+        self
+    }
+
+    pub fn with_any_headers(mut self) -> Cors {
+        self.allow_headers = None;
+        self
+    }
+
+    // Lets scripts send cookies and read the response. Credentials require origins to be
+    // listed or checked with `with_origin_fn`, with any origin this panics: every site on the
+    // web could make requests in the name of the user.
+    pub fn with_credentials(mut self, allow_credentials: bool) -> Cors {
+        self.allow_credentials = allow_credentials;
+        self.assert_no_any_origin_with_credentials();
+        self
+    }
+
+    fn assert_no_any_origin_with_credentials(&self) {
+        if matches!(self.allow_origin, AllowOrigin::Any) && self.allow_credentials {
+            panic!("CORS credentials can't be allowed for any origin, list the allowed origins");
+        }
+    }
+
+    pub fn with_max_age(mut self, max_age: Duration) -> Cors {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    // Response headers besides the safelisted ones that scripts may read
+    pub fn with_expose_headers(mut self, headers: &[&str]) -> Cors {
+        self.expose_headers = headers.iter().map(|h| h.to_string()).collect();
+        self
+    }
+
+    fn is_allowed_origin(&self, origin: &str) -> bool {
+        match &self.allow_origin {
+            AllowOrigin::Any => true,
+            AllowOrigin::List(list) => list.iter().any(|allowed| allowed == origin),
+            AllowOrigin::Predicate(predicate) => predicate(origin),
+        }
+    }
+
+    fn is_allowed_method(&self, method: &str) -> bool {
+        self.allow_methods.iter().any(|allowed| allowed == method)
+    }
+
+    fn is_allowed_header(&self, header: &str) -> bool {
+        match &self.allow_headers {
+            Some(allowed) => allowed
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(header)),
+            None => true,
+        }
+    }
+
+    fn add_origin_headers(&self, response: &mut Response, origin: &str) {
+        if matches!(self.allow_origin, AllowOrigin::Any) {
+            response.set_header("Access-Control-Allow-Origin", "*");
+        } else {
+            response.set_header("Access-Control-Allow-Origin", origin);
+        }
+        if self.allow_credentials {
+            response.set_header("Access-Control-Allow-Credentials", "true");
+        }
+    }
+
+    // Whether the answer depends on the Origin header, which caches need to know
+    fn varies_by_origin(&self) -> bool {
+        !matches!(self.allow_origin, AllowOrigin::Any)
+    }
+
+    fn preflight(&self, request: &Request, origin: &str, method: &str) -> Response {
+        let mut response = Response::new(StatusCode::NO_CONTENT);
+        response.add_vary("Origin");
+        response.add_vary("Access-Control-Request-Method");
+        response.add_vary("Access-Control-Request-Headers");
+
+        let requested_headers: Vec<&str> = request
+            .header("Access-Control-Request-Headers")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|header| !header.is_empty())
+            .collect();
+        let allowed = self.is_allowed_origin(origin)
+            && self.is_allowed_method(method)
+            && requested_headers
+                .iter()
+                .all(|header| self.is_allowed_header(header));
+        if !allowed {
+            response.status_code = StatusCode::FORBIDDEN;
+            return response;
+        }
+
+        self.add_origin_headers(&mut response, origin);
+        response.set_header(
+            "Access-Control-Allow-Methods",
+            self.allow_methods.join(", "),
+        );
+        let allow_headers = match &self.allow_headers {
+            Some(allowed) => allowed.join(", "),
+            None => requested_headers.join(", "),
+        };
+        if !allow_headers.is_empty() {
+            response.set_header("Access-Control-Allow-Headers", allow_headers);
+        }
+        if let Some(max_age) = self.max_age {
+            response.set_header("Access-Control-Max-Age", max_age.as_secs());
+        }
+        response
+    }
+}
+
+impl Middleware for Cors {
+    async fn handle(&self, request: Request, next: Next<'_>) -> Response {
+        let Some(origin) = request.header("Origin").map(str::to_string) else {
+            let mut response = next.run(request).await;
+            if self.varies_by_origin() {
+                response.add_vary("Origin");
+            }
+            return response;
+        };
+
+        if request.method == Method::OPTIONS.as_str() {
+            if let Some(method) = request.header("Access-Control-Request-Method") {
+                return self.preflight(&request, &origin, method.trim());
+            }
+        }
+
+        let mut response = next.run(request).await;
+        if self.varies_by_origin() {
+            response.add_vary("Origin");
+        }
+        if self.is_allowed_origin(&origin) {
+            self.add_origin_headers(&mut response, &origin);
+            if !self.expose_headers.is_empty() {
+                response.set_header(
+                    "Access-Control-Expose-Headers",
+                    self.expose_headers.join(", "),
+                );
+            }
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::MiddlewareStack;
+    use futures::future::LocalBoxFuture;
+
+    const ORIGIN: &str = "https://app.example.com";
+
+    fn request(method: &str, headers: &[(&str, &str)]) -> Request {
+        let mut request = Request::new();
+        request.method = method.to_string();
+        request.path = "/api".to_string();
+        for (name, value) in headers {
+            request.headers.insert(name.to_string(), value.to_string());
+        }
+        request
+    }
+
+    async fn run(cors: Cors, request: Request) -> Response {
+        let stack = MiddlewareStack::new().with(cors);
+        let endpoint = |_: Request| -> LocalBoxFuture<'_, Response> {
+            Box::pin(async {
+                let mut response = Response::text("routed");
+                response.set_header("Vary", "Accept-Encoding");
+                response
+            })
+        };
+        stack.run(request, &endpoint).await
+    }
+
+    fn app_cors() -> Cors {
+        Cors::new()
+            .with_origin(ORIGIN)
+            .with_methods(&[Method::GET, Method::PUT])
+            .with_headers(&["Content-Type", "X-Requested-With"])
+            .with_max_age(Duration::from_secs(600))
+    }
+
+    fn preflight(origin: &str, method: &str, headers: &str) -> Request {
+        request(
+            "OPTIONS",
+            &[
+                ("Origin", origin),
+                ("Access-Control-Request-Method", method),
+                ("Access-Control-Request-Headers", headers),
+            ],
+        )
+    }
+
+    #[async_std::test]
+    async fn answers_preflight_without_routing() {
+        let response = run(app_cors(), preflight(ORIGIN, "PUT", "content-type")).await;
+        assert_eq!(response.status_code, StatusCode::NO_CONTENT);
+        assert_eq!(response.body.as_bytes(), Some(&b""[..]));
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some(ORIGIN));
+        assert_eq!(
+            response.header("Access-Control-Allow-Methods"),
+            Some("GET, PUT")
+        );
+        assert_eq!(
+            response.header("Access-Control-Allow-Headers"),
+            Some("Content-Type, X-Requested-With")
+        );
+        assert_eq!(response.header("Access-Control-Max-Age"), Some("600"));
+        assert_eq!(
+            response.header("Vary"),
+            Some("Origin, Access-Control-Request-Method, Access-Control-Request-Headers")
+        );
+    }
+
+    #[async_std::test]
+    async fn any_headers_echoes_requested_headers() {
+        let cors = Cors::permissive();
+        let response = run(cors, preflight(ORIGIN, "POST", "X-One, x-two")).await;
+        assert_eq!(response.status_code, StatusCode::NO_CONTENT);
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(
+            response.header("Access-Control-Allow-Headers"),
+            Some("X-One, x-two")
+        );
+        assert_eq!(response.header("Access-Control-Max-Age"), None);
+    }
+
+    #[async_std::test]
+    async fn rejects_disallowed_preflights() {
+        let rejected = [
+            preflight("https://evil.example.com", "PUT", ""),
+            preflight(ORIGIN, "DELETE", ""),
+            preflight(ORIGIN, "PUT", "Content-Type, Authorization"),
+        ];
+        for request in rejected {
+            let response = run(app_cors(), request).await;
+            assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+            assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+            assert_eq!(response.header("Access-Control-Allow-Methods"), None);
+        }
+    }
+
+    #[async_std::test]
+    async fn simple_request_gets_allow_origin() {
+        let cors = app_cors()
+            .with_credentials(true)
+            .with_expose_headers(&["X-Total-Count"]);
+        let response = run(cors, request("GET", &[("Origin", ORIGIN)])).await;
+        assert_eq!(response.body.as_bytes(), Some(&b"routed"[..]));
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some(ORIGIN));
+        assert_eq!(
+            response.header("Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+        assert_eq!(
+            response.header("Access-Control-Expose-Headers"),
+            Some("X-Total-Count")
+        );
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding, Origin"));
+        // Preflight headers only belong to preflights
+        assert_eq!(response.header("Access-Control-Allow-Methods"), None);
+    }
+
+    #[async_std::test]
+    async fn disallowed_origin_gets_no_cors_headers() {
+        let headers = [("Origin", "https://evil.example.com")];
+        let response = run(app_cors(), request("GET", &headers)).await;
+        assert_eq!(response.body.as_bytes(), Some(&b"routed"[..]));
+        assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding, Origin"));
+    }
+
+    #[async_std::test]
+    async fn vary_only_for_origin_dependent_answers() {
+        let response = run(Cors::permissive(), request("GET", &[("Origin", ORIGIN)])).await;
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+
+        let cors = Cors::new().with_origin_fn(|origin| origin.ends_with(".example.com"));
+        let response = run(cors, request("GET", &[("Origin", ORIGIN)])).await;
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some(ORIGIN));
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding, Origin"));
+    }
+
+    #[async_std::test]
+    async fn passes_non_cors_requests_through() {
+        let response = run(Cors::permissive(), request("GET", &[])).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+
+        // An OPTIONS request without Access-Control-Request-Method is no preflight
+        let headers = [("Origin", ORIGIN)];
+        let response = run(app_cors(), request("OPTIONS", &headers)).await;
+        assert_eq!(response.body.as_bytes(), Some(&b"routed"[..]));
+    }
+
+    #[test]
+    #[should_panic(expected = "CORS credentials")]
+    fn rejects_credentials_for_any_origin() {
+        let _ = Cors::new().with_any_origin().with_credentials(true);
+    }
+
+    #[test]
+    #[should_panic(expected = "CORS credentials")]
+    fn rejects_any_origin_with_credentials() {
+        let _ = Cors::new().with_credentials(true).with_any_origin();
+    }
+
+    #[test]
+    fn allows_credentials_for_listed_origins() {
+        let cors = Cors::new()
+            .with_credentials(true)
+            .with_origin("https://app.example.com");
+        let mut response = Response::new(StatusCode::OK);
+        cors.add_origin_headers(&mut response, "https://app.example.com");
+        assert_eq!(
+            response.header("Access-Control-Allow-Origin"),
+            Some("https://app.example.com")
+        );
+        assert_eq!(
+            response.header("Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+    }
+}
@@ -0,0 +1,361 @@
+use crate::cookie::{Cookie, SameSite};
+use crate::logs::Logger;
+use crate::middleware::{Middleware, Next};
+use crate::request::{FromRequest, Request};
+use crate::response::{Response, StatusCode};
+use crate::utils::constant_time_eq;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::RngCore;
+
+pub const DEFAULT_COOKIE_NAME: &str = "surfer_csrf";
+pub const DEFAULT_HEADER_NAME: &str = "X-CSRF-Token";
+pub const DEFAULT_FIELD_NAME: &str = "csrf_token";
+
+const SAFE_METHODS: [&str; 4] = ["GET", "HEAD", "OPTIONS", "TRACE"];
+
+// Token of the current request, to be put into forms as hidden field:
+//
+//     <input type="hidden" name="csrf_token" value="{{ csrf_token }}">
+//
+// or sent by scripts in the `X-CSRF-Token` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsrfToken(pub String);
+
+impl CsrfToken {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromRequest for CsrfToken {
+    fn from_request(request: &Request) -> Result<Self, Response> {
+        request
+            .extensions
+            .get::<CsrfToken>()
+            .cloned()
+            .ok_or_else(|| {
+                Logger::new().error("No CSRF token found, is the Csrf middleware missing?");
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .header("Content-Type", "text/plain; charset=utf-8")
+                    .body("500 Internal Server Error")
+            })
+    }
+}
+
+// Protection against cross-site request forgery with the double-submit cookie pattern.
+// Every client gets a random token in a cookie, and requests with unsafe methods (POST,
+// PUT, DELETE, ...) have to send it back in a form field or header. Other sites can make
+// the browser send the cookie, but can't read it to put it into the request themselves.
+//
+// Unsafe requests also have to come from this site according to `Origin`, or `Referer`
+// if there is no `Origin`. Routes that are called by other servers, e.g. webhooks,
+// can be exempted.
+//
+//     server.add_middleware(Csrf::new().with_exempt("/webhooks/payments"));
+pub struct Csrf {
+    cookie_name: String,
+    header_name: String,
+    field_name: String,
+    secure: bool,
+    trusted_origins: Vec<String>,
+    exempt: Vec<String>,
+}
+
+impl Default for Csrf {
+    fn default() -> Self {
+        Csrf::new()
+    }
+}
+
+impl Csrf {
+    pub fn new() -> Csrf {
+        Csrf {
+            cookie_name: DEFAULT_COOKIE_NAME.to_string(),
+            header_name: DEFAULT_HEADER_NAME.to_string(),
+            field_name: DEFAULT_FIELD_NAME.to_string(),
+            secure: false,
+            trusted_origins: Vec::new(),
+            exempt: Vec::new(),
+        }
+    }
+
+    pub fn with_cookie_name(mut self, name: &str) -> Csrf {
+        self.cookie_name = name.to_string();
+        self
+    }
+
+    pub fn with_header_name(mut self, name: &str) -> Csrf {
+        self.header_name = name.to_string();
+        self
+    }
+
+    pub fn with_field_name(mut self, name: &str) -> Csrf {
+        self.field_name = name.to_string();
+        self
+    }
+
+    // Only send the cookie over HTTPS
+    pub fn with_secure(mut self, secure: bool) -> Csrf {
+        self.secure = secure;
+        self
+    }
+
+    // Other origins that may send unsafe requests, e.g. `https://app.example.com`
+    pub fn with_trusted_origin(mut self, origin: &str) -> Csrf {
+        self.trusted_origins
+            .push(origin.trim_end_matches('/').to_string());
+        self
+    }
+
+    // Skips the checks for this path. A trailing `*` exempts every path starting with the rest.
+    pub fn with_exempt(mut self, path: &str) -> Csrf {
+        self.exempt.push(path.to_string());
+        self
+    }
+
+    fn is_exempt(&self, request: &Request) -> bool {
+        let path = request.path.split('?').next().unwrap_or_default();
+        self.exempt
+            .iter()
+            .any(|exempt| match exempt.strip_suffix('*') {
+                Some(prefix) => path.starts_with(prefix),
+                None => path == exempt,
+            })
+    }
+
+    // Requests without `Origin` and `Referer` pass, some browsers and privacy tools strip them.
+    // The token still has to match for those.
+    fn check_origin(&self, request: &Request) -> Result<(), &'static str> {
+        let (source, error) = match (request.header("Origin"), request.header("Referer")) {
+            (Some(origin), _) => (origin, "origin not allowed"),
+            (None, Some(referer)) => (referer, "referer not allowed"),
+            (None, None) => return Ok(()),
+        };
+        let Some((scheme, rest)) = source.split_once("://") else {
+            // e.g. `Origin: null` of sandboxed documents
+            return Err(error);
+        };
+        let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+        let origin = format!("{}://{}", scheme, host);
+        let same_host = request
+            .header("Host")
+            .is_some_and(|expected| expected.eq_ignore_ascii_case(host));
+        let trusted = self
+            .trusted_origins
+            .iter()
+            .any(|trusted| trusted.eq_ignore_ascii_case(&origin));
+        if same_host || trusted {
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+
+    // The token from the header, or the form field of url-encoded and multipart forms
+    fn submitted_token(&self, request: &Request) -> Option<String> {
+        if let Some(token) = request.header(&self.header_name) {
+            return Some(token.trim().to_string());
+        }
+        let media_type = request
+            .header("Content-Type")
+            .and_then(|content_type| content_type.split(';').next())
+            .unwrap_or_default()
+            .trim();
+        if media_type.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+            return form_urlencoded::parse(&request.body)
+                .find(|(name, _)| *name == self.field_name)
+                .map(|(_, value)| value.into_owned());
+        }
+        request
+            .form_data
+            .iter()
+            .find(|field| field.name == self.field_name && field.file.is_none())
+            .and_then(|field| String::from_utf8(field.data.clone()).ok())
+    }
+
+    fn cookie(&self, token: &str) -> Cookie {
+        // Scripts have to be able to read it for the header, so it's not HttpOnly
+        Cookie::new(&self.cookie_name, token)
+            .with_path("/")
+            .with_secure(self.secure)
+            .with_same_site(SameSite::Lax)
+    }
+}
+
+impl Middleware for Csrf {
+    async fn handle(&self, mut request: Request, next: Next<'_>) -> Response {
+        let cookie_token = request
+            .cookies()
+            .into_iter()
+            .find(|cookie| cookie.name == self.cookie_name && !cookie.value.is_empty())
+            .map(|cookie| cookie.value);
+
+        let is_safe = SAFE_METHODS.contains(&request.method.as_str());
+        if !is_safe && !self.is_exempt(&request) {
+            let checked = self.check_origin(&request).and_then(|_| {
+                let submitted = self.submitted_token(&request);
+                match (&cookie_token, submitted) {
+                    (Some(expected), Some(submitted))
+                        if constant_time_eq(expected.as_bytes(), submitted.as_bytes()) =>
+                    {
+                        Ok(())
+                    }
+                    (None, _) => Err("token cookie missing"),
+                    (_, None) => Err("token missing"),
+                    _ => Err("token mismatch"),
+                }
+            });
+            if let Err(reason) = checked {
+                Logger::new().info(&format!(
+                    "CSRF check failed for {} {}: {}",
+                    request.method, request.path, reason
+                ));
+                return Response::builder()
+                    .status(StatusCode::FORBIDDEN)
+                    .header("Content-Type", "text/plain; charset=utf-8")
+                    .body(format!("403 Forbidden: CSRF {}", reason));
+            }
+        }
+
+        let (token, is_new) = match cookie_token {
+            Some(token) => (token, false),
+            None => (generate_token(), true),
+        };
+        request.extensions.insert(CsrfToken(token.clone()));
+        let mut response = next.run(request).await;
+        if is_new {
+            if let Err(err) = response.add_cookie(self.cookie(&token)) {
+                Logger::new().error(&format!("Can't set the CSRF cookie: {}", err));
+            }
+        }
+        response
+    }
+}
+
+// 256 random bits, URL-safe so they need no encoding in cookies and forms
+fn generate_token() -> String {
+    let mut bytes = [0; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::MiddlewareStack;
+    use futures::future::LocalBoxFuture;
+
+    const TOKEN: &str = "c3VyZmVyLWNzcmYtdGVzdC10b2tlbg";
+
+    fn request(method: &str, headers: &[(&str, &str)], body: &str) -> Request {
+        let mut request = Request::new();
+        request.method = method.to_string();
+        request.path = "/profile".to_string();
+        request
+            .headers
+            .insert("Host".to_string(), "app.example.com".to_string());
+        for (name, value) in headers {
+            request.headers.insert(name.to_string(), value.to_string());
+        }
+        request.body = body.as_bytes().to_vec();
+        request
+    }
+
+    async fn run(csrf: Csrf, request: Request) -> Response {
+        let stack = MiddlewareStack::new().with(csrf);
+        let endpoint = |request: Request| -> LocalBoxFuture<'_, Response> {
+            Box::pin(async move {
+                let token = CsrfToken::from_request(&request).unwrap();
+                Response::text(token.0)
+            })
+        };
+        stack.run(request, &endpoint).await
+    }
+
+    #[async_std::test]
+    async fn safe_requests_get_a_token() {
+        let response = run(Csrf::new(), request("GET", &[], "")).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let cookie = &response.cookies[0];
+        assert_eq!(cookie.name, DEFAULT_COOKIE_NAME);
+        assert_eq!(response.body.as_bytes(), Some(cookie.value.as_bytes()));
+    }
+
+    #[async_std::test]
+    async fn accepts_matching_token() {
+        let cookie = format!("{}={}", DEFAULT_COOKIE_NAME, TOKEN);
+        let headers = [("Cookie", cookie.as_str()), ("X-CSRF-Token", TOKEN)];
+        let response = run(Csrf::new(), request("POST", &headers, "")).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        // The client already has the cookie
+        assert!(response.cookies.is_empty());
+
+        let headers = [
+            ("Cookie", cookie.as_str()),
+            (
+                "Content-Type",
+                "Application/X-WWW-Form-Urlencoded; charset=UTF-8",
+            ),
+        ];
+        let body = format!("name=alice&csrf_token={}", TOKEN);
+        let response = run(Csrf::new(), request("POST", &headers, &body)).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+    }
+
+    #[async_std::test]
+    async fn rejects_mismatched_or_missing_token() {
+        let cookie = format!("{}={}", DEFAULT_COOKIE_NAME, TOKEN);
+        let headers = [("Cookie", cookie.as_str()), ("X-CSRF-Token", "forged")];
+        let response = run(Csrf::new(), request("POST", &headers, "")).await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+        assert_eq!(
+            response.body.as_bytes(),
+            Some(&b"403 Forbidden: CSRF token mismatch"[..])
+        );
+
+        let headers = [("Cookie", cookie.as_str())];
+        let response = run(Csrf::new(), request("DELETE", &headers, "")).await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+
+        let headers = [("X-CSRF-Token", TOKEN)];
+        let response = run(Csrf::new(), request("POST", &headers, "")).await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+    }
+
+    #[async_std::test]
+    async fn rejects_cross_origin_requests() {
+        let cookie = format!("{}={}", DEFAULT_COOKIE_NAME, TOKEN);
+        let headers = [
+            ("Cookie", cookie.as_str()),
+            ("X-CSRF-Token", TOKEN),
+            ("Origin", "https://evil.example.com"),
+        ];
+        let response = run(Csrf::new(), request("POST", &headers, "")).await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+        assert_eq!(
+            response.body.as_bytes(),
+            Some(&b"403 Forbidden: CSRF origin not allowed"[..])
+        );
+
+        let trusted = Csrf::new().with_trusted_origin("https://evil.example.com/");
+        let response = run(trusted, request("POST", &headers, "")).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        let headers = [
+            ("Cookie", cookie.as_str()),
+            ("X-CSRF-Token", TOKEN),
+            ("Referer", "https://evil.example.com/form"),
+        ];
+        let response = run(Csrf::new(), request("POST", &headers, "")).await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+    }
+
+    #[async_std::test]
+    async fn skips_exempt_paths() {
+        let csrf = Csrf::new().with_exempt("/prof*");
+        let response = run(csrf, request("POST", &[], "")).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+    }
+}
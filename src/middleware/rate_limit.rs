@@ -0,0 +1,381 @@
+use crate::logs::Logger;
+use crate::middleware::{route_entry, Middleware, Next};
+use crate::request::{Method, Request};
+use crate::response::{Response, StatusCode};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+// How many requests a client may send: `limit` per `period`, replenished evenly over the
+// period, with up to `burst` requests at once (by default the whole `limit`).
+//
+// Requests are limited with the generic cell rate algorithm (GCRA), which behaves like
+// a token bucket but only needs to keep a single timestamp per client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    limit: u32,
+    period: Duration,
+    burst: u32,
+}
+
+impl Quota {
+    pub fn new(limit: u32, period: Duration) -> Quota {
+        let limit = limit.max(1);
+        Quota {
+            limit,
+            period,
+            burst: limit,
+        }
+    }
+
+    pub fn per_second(limit: u32) -> Quota {
+        Quota::new(limit, Duration::from_secs(1))
+    }
+
+    pub fn per_minute(limit: u32) -> Quota {
+        Quota::new(limit, Duration::from_secs(60))
+    }
+
+    pub fn per_hour(limit: u32) -> Quota {
+        Quota::new(limit, Duration::from_secs(60 * 60))
+    }
+
+    pub fn with_burst(mut self, burst: u32) -> Quota {
+        self.burst = burst.max(1);
+        self
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    pub fn burst(&self) -> u32 {
+        self.burst
+    }
+
+    // Time it takes to earn back a single request
+    fn interval(&self) -> Duration {
+        (self.period / self.limit).max(Duration::from_nanos(1))
+    }
+
+    // One step of the GCRA. `tat` is the "theoretical arrival time" stored for the client,
+    // the time at which its quota is completely replenished. Returns the decision and,
+    // if the request is allowed, the new `tat` to store.
+    //
+    // Stores other than `MemoryStore` can use this to implement `RateLimitStore::check`.
+    pub fn check(
+        &self,
+        tat: Option<SystemTime>,
+        now: SystemTime,
+    ) -> (RateLimitDecision, Option<SystemTime>) {
+        let interval = self.interval();
+        let capacity = interval * self.burst;
+        let delay = tat
+            .and_then(|tat| tat.duration_since(now).ok())
+            .unwrap_or_default();
+        let new_delay = delay + interval;
+        if new_delay > capacity {
+            let decision = RateLimitDecision {
+                allowed: false,
+                limit: self.burst,
+                remaining: 0,
+                reset_after: delay,
+                retry_after: Some(new_delay - capacity),
+            };
+            return (decision, None);
+        }
+        let decision = RateLimitDecision {
+            allowed: true,
+            limit: self.burst,
+            remaining: ((capacity - new_delay).as_nanos() / interval.as_nanos()) as u32,
+            reset_after: new_delay,
+            retry_after: None,
+        };
+        (decision, Some(now + new_delay))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    // Most requests the client can have left
+    pub limit: u32,
+    pub remaining: u32,
+    // Until the client has its whole quota back
+    pub reset_after: Duration,
+    // Until the next request is allowed, `None` if this one was
+    pub retry_after: Option<Duration>,
+}
+
+// Keeps track of how much of its quota each client has used, see `MemoryStore`
+pub trait RateLimitStore: Send + Sync {
+    // Counts a request of `key` against `quota`. Stores shared by several servers
+    // have to do this atomically, otherwise clients can exceed their quota.
+    fn check(
+        &self,
+        key: &str,
+        quota: &Quota,
+    ) -> impl Future<Output = io::Result<RateLimitDecision>> + Send;
+}
+
+// Keeps the state in memory, so it is lost on restart and not shared between servers
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    state: Mutex<MemoryState>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryState {
+    tats: HashMap<String, SystemTime>,
+    // Clients with a replenished quota are dropped once the map grows this large
+    next_cleanup: usize,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl RateLimitStore for MemoryStore {
+    async fn check(&self, key: &str, quota: &Quota) -> io::Result<RateLimitDecision> {
+        let now = SystemTime::now();
+        let mut state = self.state.lock().unwrap();
+        if state.tats.len() >= state.next_cleanup {
+            state.tats.retain(|_, tat| *tat > now);
+            state.next_cleanup = (state.tats.len() * 2).max(1024);
+        }
+        let (decision, tat) = quota.check(state.tats.get(key).copied(), now);
+        if let Some(tat) = tat {
+            state.tats.insert(key.to_string(), tat);
+        }
+        Ok(decision)
+    }
+}
+
+type KeyFn = Box<dyn Fn(&Request) -> Option<String>>;
+
+enum ClientKey {
+    PeerIp,
+    Header(String),
+    Custom(KeyFn),
+}
+
+// Limits how many requests each client can send. Clients are told about their quota with
+// the `RateLimit-*` headers and get a 429 Too Many Requests once it's used up.
+//
+//     server.add_middleware(
+//         RateLimit::new(MemoryStore::new(), Quota::per_minute(120))
+//             .with_route(Method::POST, "/login", Quota::per_minute(5)),
+//     );
+//
+// Clients are told apart by IP address unless configured otherwise. Behind a reverse proxy
+// that's the proxy's address, so key by a header the proxy sets instead.
+pub struct RateLimit<S> {
+    store: S,
+    quota: Quota,
+    route_quotas: HashMap<String, Quota>,
+    key: ClientKey,
+}
+
+impl<S: RateLimitStore> RateLimit<S> {
+    pub fn new(store: S, quota: Quota) -> RateLimit<S> {
+        RateLimit {
+            store,
+            quota,
+            route_quotas: HashMap::new(),
+            key: ClientKey::PeerIp,
+        }
+    }
+
+    // Gives the route its own quota, which is counted separately from the default one
+    pub fn with_route(mut self, method: Method, path: &str, quota: Quota) -> RateLimit<S> {
+        self.route_quotas
+            .insert(format!("{} {}", method, path), quota);
+        self
+    }
+
+    // e.g. `X-Api-Key` or `X-Forwarded-For`
+    pub fn with_key_header(mut self, name: &str) -> RateLimit<S> {
+        self.key = ClientKey::Header(name.to_string());
+        self
+    }
+
+    // Derives the key from the request, e.g. with an extractor:
+    //
+    //     .with_key_fn(|request| Some(ApiUser::from_request(request).ok()?.id))
+    pub fn with_key_fn<F>(mut self, key: F) -> RateLimit<S>
+    where
+        F: Fn(&Request) -> Option<String> + 'static,
+    {
+        self.key = ClientKey::Custom(Box::new(key));
+        self
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    // Requests without a key of their own are limited by IP address
+    fn client_key(&self, request: &Request) -> Option<String> {
+        let key = match &self.key {
+            ClientKey::PeerIp => None,
+            ClientKey::Header(name) => request.header(name).map(str::to_string),
+            ClientKey::Custom(key) => key(request),
+        };
+        key.or_else(|| request.peer_addr.map(|addr| addr.ip().to_string()))
+    }
+}
+
+impl<S: RateLimitStore> Middleware for RateLimit<S> {
+    async fn handle(&self, request: Request, next: Next<'_>) -> Response {
+        let Some(client) = self.client_key(&request) else {
+            return next.run(request).await;
+        };
+        let (scope, quota) =
+            route_entry(&self.route_quotas, &request).unwrap_or(("*", &self.quota));
+
+        let decision = match self
+            .store
+            .check(&format!("{}|{}", scope, client), quota)
+            .await
+        {
+            Ok(decision) => decision,
+            // Better to let everyone through than to lock everyone out
+            Err(err) => {
+                Logger::new().error(&format!("Error checking rate limit: {}", err));
+                return next.run(request).await;
+            }
+        };
+        if !decision.allowed {
+            let mut response = Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header("Content-Type", "text/plain; charset=utf-8")
+                .body("429 Too Many Requests");
+            let retry_after = decision.retry_after.unwrap_or_default();
+            response.set_header("Retry-After", seconds(retry_after).max(1).to_string());
+            set_rate_limit_headers(&mut response, quota, &decision);
+            return response;
+        }
+        let mut response = next.run(request).await;
+        set_rate_limit_headers(&mut response, quota, &decision);
+        response
+    }
+}
+
+// Headers of the IETF draft "RateLimit header fields for HTTP"
+fn set_rate_limit_headers(response: &mut Response, quota: &Quota, decision: &RateLimitDecision) {
+    response.set_header("RateLimit-Limit", decision.limit.to_string());
+    response.set_header("RateLimit-Remaining", decision.remaining.to_string());
+    response.set_header("RateLimit-Reset", seconds(decision.reset_after).to_string());
+    response.set_header(
+        "RateLimit-Policy",
+        format!("{};w={}", quota.limit, seconds(quota.period)),
+    );
+}
+
+// Rounded up, so clients don't come back too early
+fn seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::MiddlewareStack;
+    use futures::future::LocalBoxFuture;
+
+    #[test]
+    fn allows_the_burst_then_limits() {
+        let quota = Quota::per_minute(3);
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let mut tat = None;
+        for remaining in [2, 1, 0] {
+            let (decision, new_tat) = quota.check(tat, now);
+            assert!(decision.allowed);
+            assert_eq!(decision.remaining, remaining);
+            tat = new_tat;
+        }
+
+        let (decision, new_tat) = quota.check(tat, now);
+        assert!(!decision.allowed);
+        assert_eq!(decision.retry_after, Some(Duration::from_secs(20)));
+        assert_eq!(decision.reset_after, Duration::from_secs(60));
+        assert_eq!(new_tat, None);
+    }
+
+    #[test]
+    fn replenishes_one_request_per_interval() {
+        let quota = Quota::per_minute(3);
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let mut tat = None;
+        for _ in 0..3 {
+            tat = quota.check(tat, now).1;
+        }
+
+        let later = now + Duration::from_secs(20);
+        let (decision, tat) = quota.check(tat, later);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 0);
+        assert!(!quota.check(tat, later).0.allowed);
+    }
+
+    #[test]
+    fn burst_can_be_smaller_than_the_limit() {
+        let quota = Quota::per_minute(60).with_burst(5);
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let mut tat = None;
+        for _ in 0..5 {
+            let (decision, new_tat) = quota.check(tat, now);
+            assert!(decision.allowed);
+            tat = new_tat;
+        }
+        let (decision, _) = quota.check(tat, now);
+        assert!(!decision.allowed);
+        assert_eq!(decision.retry_after, Some(Duration::from_secs(1)));
+    }
+
+    #[async_std::test]
+    async fn memory_store_counts_keys_separately() {
+        let store = MemoryStore::new();
+        let quota = Quota::per_minute(1);
+        assert!(store.check("a", &quota).await.unwrap().allowed);
+        assert!(!store.check("a", &quota).await.unwrap().allowed);
+        assert!(store.check("b", &quota).await.unwrap().allowed);
+    }
+
+    async fn status(stack: &MiddlewareStack, method: &str, path: &str) -> StatusCode {
+        let mut request = Request::new();
+        request.method = method.to_string();
+        request.path = path.to_string();
+        request.peer_addr = Some(([127, 0, 0, 1], 4000).into());
+        let endpoint = |_: Request| -> LocalBoxFuture<'_, Response> {
+            Box::pin(async { Response::text("ok") })
+        };
+        stack.run(request, &endpoint).await.status_code
+    }
+
+    #[async_std::test]
+    async fn head_requests_count_against_the_get_quota() {
+        let stack = MiddlewareStack::new().with(
+            RateLimit::new(MemoryStore::new(), Quota::per_minute(100)).with_route(
+                Method::GET,
+                "/login",
+                Quota::per_minute(1),
+            ),
+        );
+        assert_eq!(status(&stack, "GET", "/login").await, StatusCode::OK);
+        assert_eq!(
+            status(&stack, "HEAD", "/login?next=/").await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        // Other routes use the default quota
+        assert_eq!(status(&stack, "HEAD", "/").await, StatusCode::OK);
+    }
+}
@@ -0,0 +1,437 @@
+use crate::logs::Logger;
+use crate::middleware::{route_entry, Middleware, Next};
+use crate::request::{FromRequest, Method, Request};
+use crate::response::{Response, StatusCode};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use rand::RngCore;
+use std::collections::HashMap;
+use std::time::Duration;
+
+// Nonce of the current request's `Content-Security-Policy`. Inline scripts and styles
+// only run if they carry it:
+//
+//     <script nonce="{{ csp_nonce }}">...</script>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CspNonce(pub String);
+
+impl CspNonce {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromRequest for CspNonce {
+    fn from_request(request: &Request) -> Result<Self, Response> {
+        request
+            .extensions
+            .get::<CspNonce>()
+            .cloned()
+            .ok_or_else(|| {
+                Logger::new().error(
+                    "No CSP nonce found, is the SecurityHeaders middleware missing or without nonce?",
+                );
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .header("Content-Type", "text/plain; charset=utf-8")
+                    .body("500 Internal Server Error")
+            })
+    }
+}
+
+// Builder for `Content-Security-Policy` headers. Sources are written as in the header,
+// keywords in single quotes:
+//
+//     ContentSecurityPolicy::new()
+//         .with_directive("default-src", &["'self'"])
+//         .with_directive("img-src", &["'self'", "data:", "https://cdn.example.com"])
+//         .with_nonce("script-src")
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContentSecurityPolicy {
+    directives: Vec<(String, Vec<String>)>,
+    // Directives that get the per-request nonce added
+    nonce_directives: Vec<String>,
+    report_only: bool,
+}
+
+impl ContentSecurityPolicy {
+    pub fn new() -> ContentSecurityPolicy {
+        ContentSecurityPolicy::default()
+    }
+
+    // Replaces the sources of the directive if it's already set.
+    // Directives without sources, like `upgrade-insecure-requests`, take an empty list.
+    pub fn with_directive(mut self, name: &str, sources: &[&str]) -> ContentSecurityPolicy {
+        let sources = sources.iter().map(|source| source.to_string()).collect();
+        match self
+            .directives
+            .iter_mut()
+            .find(|(directive, _)| directive.eq_ignore_ascii_case(name))
+        {
+            Some((_, existing)) => *existing = sources,
+            None => self.directives.push((name.to_ascii_lowercase(), sources)),
+        }
+        self
+    }
+
+    pub fn without_directive(mut self, name: &str) -> ContentSecurityPolicy {
+        self.directives
+            .retain(|(directive, _)| !directive.eq_ignore_ascii_case(name));
+        self
+    }
+
+    // Adds a fresh `'nonce-...'` source to the directive for every request, e.g. `script-src`.
+    // The nonce is available to handlers as `CspNonce`.
+    pub fn with_nonce(mut self, directive: &str) -> ContentSecurityPolicy {
+        self.nonce_directives.push(directive.to_ascii_lowercase());
+        self
+    }
+
+    // Reports violations instead of blocking, to try out a policy
+    pub fn with_report_only(mut self, report_only: bool) -> ContentSecurityPolicy {
+        self.report_only = report_only;
+        self
+    }
+
+    pub fn has_directive(&self, name: &str) -> bool {
+        self.directives
+            .iter()
+            .any(|(directive, _)| directive.eq_ignore_ascii_case(name))
+    }
+
+    pub fn uses_nonce(&self) -> bool {
+        !self.nonce_directives.is_empty()
+    }
+
+    pub fn header_name(&self) -> &'static str {
+        if self.report_only {
+            "Content-Security-Policy-Report-Only"
+        } else {
+            "Content-Security-Policy"
+        }
+    }
+
+    pub fn render(&self, nonce: Option<&str>) -> String {
+        let mut directives = self.directives.clone();
+        if let Some(nonce) = nonce {
+            let default_sources = directives
+                .iter()
+                .find(|(directive, _)| directive == "default-src")
+                .map(|(_, sources)| sources.clone())
+                .unwrap_or_default();
+            for name in &self.nonce_directives {
+                let source = format!("'nonce-{}'", nonce);
+                match directives
+                    .iter_mut()
+                    .find(|(directive, _)| directive == name)
+                {
+                    Some((_, sources)) => sources.push(source),
+                    // A new directive replaces `default-src` for its resources, so it starts out
+                    // with the same sources
+                    None => {
+                        let mut sources = default_sources.clone();
+                        sources.push(source);
+                        directives.push((name.clone(), sources));
+                    }
+                }
+            }
+        }
+        directives
+            .iter()
+            .map(|(name, sources)| {
+                let mut directive = name.clone();
+                for source in sources {
+                    directive.push(' ');
+                    directive.push_str(source);
+                }
+                directive
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+// `Strict-Transport-Security`, makes browsers use HTTPS for every request to the host.
+// Only takes effect on responses sent over HTTPS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hsts {
+    pub max_age: Duration,
+    pub include_subdomains: bool,
+    // Asks for inclusion in the browsers' preload lists, which is hard to undo
+    pub preload: bool,
+}
+
+impl Default for Hsts {
+    fn default() -> Self {
+        Hsts {
+            max_age: Duration::from_secs(365 * 24 * 60 * 60),
+            include_subdomains: true,
+            preload: false,
+        }
+    }
+}
+
+impl Hsts {
+    fn render(&self) -> String {
+        let mut value = format!("max-age={}", self.max_age.as_secs());
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if self.preload {
+            value.push_str("; preload");
+        }
+        value
+    }
+}
+
+// Who may embed the pages in frames, protects against clickjacking
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameOptions {
+    Deny,
+    SameOrigin,
+}
+
+// Sets the usual security headers on every response:
+//
+// - `Content-Security-Policy: default-src 'self'; base-uri 'self'; form-action 'self'; object-src 'none'`
+//   plus `frame-ancestors` matching the frame options
+// - `Strict-Transport-Security: max-age=31536000; includeSubDomains`
+// - `X-Content-Type-Options: nosniff`
+// - `Referrer-Policy: strict-origin-when-cross-origin`
+// - `Permissions-Policy: camera=(), microphone=(), geolocation=()`
+// - `X-Frame-Options: DENY`
+//
+// Headers a handler already set are left alone, and routes can get a configuration of their own:
+//
+//     server.add_middleware(
+//         SecurityHeaders::new()
+//             .with_csp(Some(
+//                 ContentSecurityPolicy::new()
+//                     .with_directive("default-src", &["'self'"])
+//                     .with_nonce("script-src"),
+//             ))
+//             .with_route(Method::GET, "/embed", SecurityHeaders::new().with_frame_options(None)),
+//     );
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    csp: Option<ContentSecurityPolicy>,
+    hsts: Option<Hsts>,
+    no_sniff: bool,
+    referrer_policy: Option<String>,
+    permissions_policy: Option<String>,
+    frame_options: Option<FrameOptions>,
+    routes: HashMap<String, SecurityHeaders>,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        SecurityHeaders::new()
+    }
+}
+
+impl SecurityHeaders {
+    pub fn new() -> SecurityHeaders {
+        SecurityHeaders {
+            csp: Some(
+                ContentSecurityPolicy::new()
+                    .with_directive("default-src", &["'self'"])
+                    .with_directive("base-uri", &["'self'"])
+                    .with_directive("form-action", &["'self'"])
+                    .with_directive("object-src", &["'none'"]),
+            ),
+            hsts: Some(Hsts::default()),
+            no_sniff: true,
+            referrer_policy: Some("strict-origin-when-cross-origin".to_string()),
+            permissions_policy: Some("camera=(), microphone=(), geolocation=()".to_string()),
+            frame_options: Some(FrameOptions::Deny),
+            routes: HashMap::new(),
+        }
+    }
+
+    // `None` for no `Content-Security-Policy` header
+    pub fn with_csp(mut self, csp: Option<ContentSecurityPolicy>) -> SecurityHeaders {
+        self.csp = csp;
+        self
+    }
+
+    pub fn with_hsts(mut self, hsts: Option<Hsts>) -> SecurityHeaders {
+        self.hsts = hsts;
+        self
+    }
+
+    pub fn with_no_sniff(mut self, no_sniff: bool) -> SecurityHeaders {
+        self.no_sniff = no_sniff;
+        self
+    }
+
+    pub fn with_referrer_policy(mut self, policy: Option<&str>) -> SecurityHeaders {
+        self.referrer_policy = policy.map(str::to_string);
+        self
+    }
+
+    // e.g. `camera=(), fullscreen=(self)`
+    pub fn with_permissions_policy(mut self, policy: Option<&str>) -> SecurityHeaders {
+        self.permissions_policy = policy.map(str::to_string);
+        self
+    }
+
+    // Sets `X-Frame-Options` and, unless the policy has its own, the CSP's `frame-ancestors`
+    pub fn with_frame_options(mut self, frame_options: Option<FrameOptions>) -> SecurityHeaders {
+        self.frame_options = frame_options;
+        self
+    }
+
+    // Uses `headers` instead for this route. Routes of `headers` itself are ignored.
+    pub fn with_route(
+        mut self,
+        method: Method,
+        path: &str,
+        headers: SecurityHeaders,
+    ) -> SecurityHeaders {
+        self.routes.insert(format!("{} {}", method, path), headers);
+        self
+    }
+
+    fn apply(&self, response: &mut Response, nonce: Option<&str>) {
+        // A handler's own `X-Frame-Options` has to end up in `frame-ancestors` as well,
+        // browsers that support both ignore `X-Frame-Options`
+        let frame_options = match response.header("X-Frame-Options") {
+            Some(value) if value.eq_ignore_ascii_case("DENY") => Some(FrameOptions::Deny),
+            Some(value) if value.eq_ignore_ascii_case("SAMEORIGIN") => {
+                Some(FrameOptions::SameOrigin)
+            }
+            _ => self.frame_options,
+        };
+        let mut headers = Vec::new();
+        if let Some(csp) = &self.csp {
+            let csp = match frame_options {
+                Some(frame_options) if !csp.has_directive("frame-ancestors") => {
+                    let ancestors = match frame_options {
+                        FrameOptions::Deny => "'none'",
+                        FrameOptions::SameOrigin => "'self'",
+                    };
+                    csp.clone().with_directive("frame-ancestors", &[ancestors])
+                }
+                _ => csp.clone(),
+            };
+            headers.push((csp.header_name(), csp.render(nonce)));
+        }
+        if let Some(hsts) = &self.hsts {
+            headers.push(("Strict-Transport-Security", hsts.render()));
+        }
+        if self.no_sniff {
+            headers.push(("X-Content-Type-Options", "nosniff".to_string()));
+        }
+        if let Some(policy) = &self.referrer_policy {
+            headers.push(("Referrer-Policy", policy.clone()));
+        }
+        if let Some(policy) = &self.permissions_policy {
+            headers.push(("Permissions-Policy", policy.clone()));
+        }
+        if let Some(frame_options) = self.frame_options {
+            let value = match frame_options {
+                FrameOptions::Deny => "DENY",
+                FrameOptions::SameOrigin => "SAMEORIGIN",
+            };
+            headers.push(("X-Frame-Options", value.to_string()));
+        }
+        for (name, value) in headers {
+            if response.header(name).is_none() {
+                response.set_header(name, value);
+            }
+        }
+    }
+}
+
+impl Middleware for SecurityHeaders {
+    async fn handle(&self, mut request: Request, next: Next<'_>) -> Response {
+        let headers = route_entry(&self.routes, &request)
+            .map(|(_, headers)| headers)
+            .unwrap_or(self);
+
+        let nonce = headers
+            .csp
+            .as_ref()
+            .filter(|csp| csp.uses_nonce())
+            .map(|_| generate_nonce());
+        if let Some(nonce) = &nonce {
+            request.extensions.insert(CspNonce(nonce.clone()));
+        }
+        let mut response = next.run(request).await;
+        headers.apply(&mut response, nonce.as_deref());
+        response
+    }
+}
+
+// 128 random bits, as recommended by the CSP spec
+fn generate_nonce() -> String {
+    let mut bytes = [0; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    STANDARD.encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::MiddlewareStack;
+    use futures::future::LocalBoxFuture;
+
+    #[test]
+    fn render_without_nonce() {
+        let csp = ContentSecurityPolicy::new()
+            .with_directive("default-src", &["'self'"])
+            .with_directive("upgrade-insecure-requests", &[])
+            .with_nonce("script-src");
+        assert_eq!(
+            csp.render(None),
+            "default-src 'self'; upgrade-insecure-requests"
+        );
+    }
+
+    #[test]
+    fn render_adds_nonce_to_existing_directive() {
+        let csp = ContentSecurityPolicy::new()
+            .with_directive("default-src", &["'self'"])
+            .with_directive("Script-Src", &["'self'", "https://cdn.example.com"])
+            .with_nonce("script-src");
+        assert_eq!(
+            csp.render(Some("abc")),
+            "default-src 'self'; script-src 'self' https://cdn.example.com 'nonce-abc'"
+        );
+    }
+
+    #[test]
+    fn render_copies_default_src_for_new_directive() {
+        let csp = ContentSecurityPolicy::new()
+            .with_directive("default-src", &["'self'", "https:"])
+            .with_nonce("style-src");
+        assert_eq!(
+            csp.render(Some("abc")),
+            "default-src 'self' https:; style-src 'self' https: 'nonce-abc'"
+        );
+    }
+
+    #[async_std::test]
+    async fn head_requests_use_the_get_route() {
+        let stack = MiddlewareStack::new().with(SecurityHeaders::new().with_route(
+            Method::GET,
+            "/embed",
+            SecurityHeaders::new().with_frame_options(None),
+        ));
+        let endpoint =
+            |_: Request| -> LocalBoxFuture<'_, Response> { Box::pin(async { Response::text("") }) };
+        for method in ["GET", "HEAD"] {
+            let mut request = Request::new();
+            request.method = method.to_string();
+            request.path = "/embed?page=1".to_string();
+            let response = stack.run(request, &endpoint).await;
+            assert_eq!(response.header("X-Frame-Options"), None);
+        }
+
+        let mut request = Request::new();
+        request.method = "HEAD".to_string();
+        request.path = "/".to_string();
+        let response = stack.run(request, &endpoint).await;
+        assert_eq!(response.header("X-Frame-Options"), Some("DENY"));
+    }
+}
@@ -1,6 +1,27 @@
+pub mod accept;
+mod decompress;
+pub mod extensions;
+pub mod form;
+pub mod json;
+pub mod multipart;
+
+use async_std::io::Read;
 use async_std::net::TcpStream;
+use async_std::path::PathBuf;
 use async_std::prelude::*;
+use async_std::task;
 use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+
+use crate::cookie::{parse_cookie_header, Cookie};
+use crate::response::{Response, StatusCode};
+
+pub use extensions::Extensions;
+pub use multipart::UploadedFile;
+
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+pub const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 16 * 1024 * 1024;
 
 #[derive(Debug,Clone)]
 pub enum Method {
@@ -31,28 +52,108 @@ impl Method {
         }
     }
 
-    pub fn to_string(&self) -> String {
+    pub fn as_str(&self) -> &'static str {
         match self {
-            Method::GET => "GET".to_string(),
-            Method::POST => "POST".to_string(),
-            Method::PUT => "PUT".to_string(),
-            Method::DELETE => "DELETE".to_string(),
-            Method::PATCH => "PATCH".to_string(),
-            Method::HEAD => "HEAD".to_string(),
-            Method::OPTIONS => "OPTIONS".to_string(),
-            Method::CONNECT => "CONNECT".to_string(),
-            Method::TRACE => "TRACE".to_string(),
+            Method::GET => "GET",
+            Method::POST => "POST",
+            Method::PUT => "PUT",
+            Method::DELETE => "DELETE",
+            Method::PATCH => "PATCH",
+            Method::HEAD => "HEAD",
+            Method::OPTIONS => "OPTIONS",
+            Method::CONNECT => "CONNECT",
+            Method::TRACE => "TRACE",
         }
     }
 }
 
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Types that can be built from an incoming request, e.g. a deserialized body.
+// If extraction fails, the returned response should be sent back as is.
+pub trait FromRequest: Sized {
+    // Rejections are rare, so the size of `Response` doesn't matter here
+    #[allow(clippy::result_large_err)]
+    fn from_request(request: &Request) -> Result<Self, Response>;
+}
+
 #[derive(Debug)]
 pub struct FormData {
     pub name: String,
     pub filename: Option<String>,
+    // Content-Type sent along with the part, if any
+    pub content_type: Option<String>,
+    // Empty if the part has been spooled to `file`
     pub data: Vec<u8>,
+    pub file: Option<UploadedFile>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestConfig {
+    // Multipart file parts larger than this are written to a temporary file
+    // in `upload_dir` instead of being kept in memory. `None` keeps everything in memory.
+    pub spool_threshold: Option<usize>,
+    pub upload_dir: PathBuf,
+    // Multipart bodies with a part larger than this, or all parts together larger than
+    // `max_multipart_size`, are rejected. `None` for no limit.
+    pub max_part_size: Option<usize>,
+    pub max_multipart_size: Option<usize>,
+    // Bodies sent with `Content-Encoding` are decompressed up to this many bytes,
+    // larger ones are rejected with 413 Payload Too Large
+    pub max_decompressed_size: usize,
+}
+
+impl Default for RequestConfig {
+    fn default() -> Self {
+        RequestConfig {
+            spool_threshold: None,
+            upload_dir: std::env::temp_dir().into(),
+            max_part_size: None,
+            max_multipart_size: None,
+            max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+        }
+    }
+}
+
+// Why a request couldn't be parsed
+#[derive(Debug)]
+pub enum ParseError {
+    // Not a valid HTTP request, or the connection was closed early
+    Malformed,
+    // The body uses a `Content-Encoding` other than gzip, deflate or br
+    UnsupportedEncoding(String),
+    // The decompressed body exceeds `RequestConfig::max_decompressed_size`
+    BodyTooLarge,
+}
+
+impl ParseError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ParseError::Malformed => StatusCode::BAD_REQUEST,
+            ParseError::UnsupportedEncoding(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ParseError::BodyTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Malformed => f.write_str("malformed request"),
+            ParseError::UnsupportedEncoding(encoding) => {
+                write!(f, "unsupported content encoding '{}'", encoding)
+            }
+            ParseError::BodyTooLarge => f.write_str("decompressed request body is too large"),
+        }
+    }
 }
 
+impl std::error::Error for ParseError {}
+
 #[derive(Debug)]
 pub struct Request {
     pub method: String,
@@ -61,6 +162,16 @@ pub struct Request {
     pub body: Vec<u8>,
     pub form_data: Vec<FormData>,
     pub http_version: String,
+    // Address of the client the connection came from, `None` for requests built by hand
+    pub peer_addr: Option<SocketAddr>,
+    // Set by middleware for the handlers further down
+    pub extensions: Extensions,
+}
+
+impl Default for Request {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Request {
@@ -72,76 +183,153 @@ impl Request {
             body: Vec::new(),
             form_data: Vec::new(),
             http_version: String::new(),
+            peer_addr: None,
+            extensions: Extensions::new(),
         }
     }
 
-    pub async fn parse(&mut self, stream: &mut TcpStream) -> Result<(), ()> {
-        // First parse the header line to get the method, path and HTTP version
-        let mut buffer = [0; 1024];
-        let bytes_read = stream.read(&mut buffer).await.unwrap();
+    pub async fn parse(&mut self, stream: &mut TcpStream) -> Result<(), ParseError> {
+        self.parse_with_config(stream, &RequestConfig::default())
+            .await
+    }
+
+    pub async fn parse_with_config(
+        &mut self,
+        stream: &mut TcpStream,
+        config: &RequestConfig,
+    ) -> Result<(), ParseError> {
+        // Read until the end of the headers, anything after that already belongs to the body
+        let mut buffer = Vec::new();
+        let mut chunk = [0; 1024];
+        let header_length = loop {
+            let bytes_read = stream
+                .read(&mut chunk)
+                .await
+                .map_err(|_| ParseError::Malformed)?;
+            if bytes_read == 0 {
+                return Err(ParseError::Malformed);
+            }
+            buffer.extend_from_slice(&chunk[..bytes_read]);
+            if let Some(index) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+                break index + 4;
+            }
+            if buffer.len() > MAX_HEADER_SIZE {
+                return Err(ParseError::Malformed);
+            }
+        };
 
-        let buffer_request = String::from_utf8_lossy(&buffer[..bytes_read]);
+        let buffer_request = String::from_utf8_lossy(&buffer[..header_length]);
         let mut lines = buffer_request.lines();
 
         // Parse request line
         let request_line = lines.next();
         if request_line.is_none() {
-            return Err(());
+            return Err(ParseError::Malformed);
         }
-        self.process_request_line(&request_line.unwrap());
+        self.process_request_line(request_line.unwrap());
         self.process_headers(&buffer_request);
 
-        let default_content_type = "text/plain".to_string();
-        let content_type = self
-            .headers
-            .get("Content-Type")
-            .unwrap_or(&default_content_type);
-
         // get content length
-        let content_length = self.headers.get("Content-Length");
-        let content_length = content_length
-            .map(|s| s.parse::<usize>().unwrap())
-            .unwrap_or(0);
-        let header_length = buffer_request.find("\r\n\r\n").unwrap_or(0) + 4;
-        let body_length = bytes_read - header_length;
-        if content_length <= body_length {
-            let body_buffer = &buffer[header_length..header_length + content_length];
-            self.body = body_buffer.to_vec();
-        } else {
-            let mut body_buffer = vec![0; content_length];
-            // Read the beginning of body being already in the buffer
-            body_buffer[..body_length].copy_from_slice(&buffer[header_length..bytes_read]);
-            // Read the rest of the body from the stream
-            stream
-                .read_exact(&mut body_buffer[body_length..])
-                .await
-                .unwrap();
-            self.body = body_buffer.to_vec();
+        let content_length = match self.header("Content-Length") {
+            Some(length) => length
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| ParseError::Malformed)?,
+            None => 0,
+        };
+        let buffered = &buffer[header_length..buffer.len().min(header_length + content_length)];
+        let remaining = (content_length - buffered.len()) as u64;
+        let mut body = buffered.chain((&mut *stream).take(remaining));
+
+        let encodings = match self.header("Content-Encoding") {
+            Some(content_encoding) => decompress::parse_encodings(content_encoding)?,
+            None => Vec::new(),
+        };
+        if encodings.is_empty() {
+            return self.read_body(body, content_length, config).await;
         }
 
-        match content_type.split(";").collect::<Vec<&str>>()[0] {
-            "application/x-www-form-urlencoded" => {
-                let body_buffer = &buffer[header_length..bytes_read];
-                self.body = body_buffer.to_vec();
-            }
-            "multipart/form-data" => {
-                let boundary = self
-                    .headers
-                    .get("Content-Type")
-                    .unwrap()
-                    .split("boundary=")
-                    .collect::<Vec<&str>>()[1];
-                self.form_data = self.parse_multipart_form_data(&self.body, &boundary);
-            }
-            _ => {
-                let body_buffer = &buffer[header_length..bytes_read];
-                self.body = body_buffer.to_vec();
+        // Compressed bodies are decompressed as a whole before anything else looks at them,
+        // handlers see the decompressed body as if it had been sent that way
+        let mut compressed = Vec::new();
+        body.read_to_end(&mut compressed)
+            .await
+            .map_err(|_| ParseError::Malformed)?;
+        if compressed.len() < content_length {
+            return Err(ParseError::Malformed);
+        }
+        // Inflating megabytes blocks for a while, which would hold up other connections
+        let limit = config.max_decompressed_size;
+        let decompressed =
+            task::spawn_blocking(move || decompress::decompress(compressed, &encodings, limit))
+                .await?;
+        self.headers.retain(|key, _| {
+            !key.eq_ignore_ascii_case("Content-Encoding")
+                && !key.eq_ignore_ascii_case("Content-Length")
+        });
+        self.headers
+            .insert("Content-Length".to_string(), decompressed.len().to_string());
+        self.read_body(&decompressed[..], decompressed.len(), config)
+            .await
+    }
+
+    // Multipart bodies are parsed while reading, so large uploads can be spooled to disk.
+    // Any other body is read according to Content-Length,
+    // url-encoded forms are decoded on demand (see `form::Form`)
+    async fn read_body<R: Read + Unpin>(
+        &mut self,
+        mut body: R,
+        content_length: usize,
+        config: &RequestConfig,
+    ) -> Result<(), ParseError> {
+        let content_type = self
+            .header("Content-Type")
+            .unwrap_or("text/plain")
+            .to_string();
+        if content_type.split(";").collect::<Vec<&str>>()[0] == "multipart/form-data" {
+            let boundary = content_type
+                .split("boundary=")
+                .nth(1)
+                .ok_or(ParseError::Malformed)?;
+            let boundary = boundary
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim_matches('"');
+            self.form_data = multipart::parse(&mut body, boundary, config)
+                .await
+                .map_err(|_| ParseError::Malformed)?;
+        } else {
+            body.read_to_end(&mut self.body)
+                .await
+                .map_err(|_| ParseError::Malformed)?;
+            if self.body.len() < content_length {
+                return Err(ParseError::Malformed);
             }
         }
 
         Ok(())
     }
 
+    // Header names are case-insensitive, so look them up accordingly
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Cookies sent with the `Cookie` header, see `cookie::CookieJar` to change them
+    pub fn cookies(&self) -> Vec<Cookie> {
+        self.header("Cookie")
+            .map(parse_cookie_header)
+            .unwrap_or_default()
+    }
+
+    pub fn query(&self) -> Option<&str> {
+        self.path.split_once('?').map(|(_, query)| query)
+    }
+
     fn process_request_line(&mut self, request_line: &str) {
         let mut parts = request_line.split_whitespace();
         self.method = parts.next().unwrap_or("N/A").to_string();
@@ -156,72 +344,16 @@ impl Request {
             }
 
             if let Some((key, value)) = line.split_once(": ") {
-                self.headers.insert(key.to_string(), value.to_string());
-            }
-        }
-    }
-    fn parse_multipart_form_data(&self, body: &[u8], boundary: &str) -> Vec<FormData> {
-        let mut form_data: Vec<FormData> = Vec::new();
-        let boundary = format!("--{}", boundary).into_bytes();
-        let mut start_index = 0;
-
-        // We need to iterate manually over the bytes until we find the boundary
-        while let Some(boundary_index) = self.find_boundary(&body[start_index..], &boundary) {
-            let part_start = start_index + boundary_index + boundary.len();
-            if let Some(next_boundary_index) = self.find_boundary(&body[part_start..], &boundary) {
-                let part = &body[part_start..part_start + next_boundary_index];
-                if let Some(data) = self.process_part(part) {
-                    form_data.push(data);
-                }
-                start_index = part_start + next_boundary_index;
-            } else {
-                break;
-            }
-        }
-        form_data
-    }
-
-    fn find_boundary(&self, data: &[u8], boundary: &[u8]) -> Option<usize> {
-        data.windows(boundary.len())
-            .position(|window| window == boundary)
-    }
-
-    fn process_part(&self, part: &[u8]) -> Option<FormData> {
-        let split_index = part.windows(4).position(|window| window == b"\r\n\r\n")?;
-        // Split the part into headers and content (because content should be handled differently depending on the headers)
-        let (header_part, content_part) = part.split_at(split_index);
-        let headers_str = String::from_utf8_lossy(&header_part);
-        let (name, filename) = self.parse_multipart_form_data_headers(&headers_str);
-
-        Some(FormData {
-            name,
-            filename,
-            data: content_part[4..].to_vec(), // Skip the "\r\n\r\n"
-        })
-    }
-
-    fn parse_multipart_form_data_headers(&self, headers: &str) -> (String, Option<String>) {
-        let mut name = String::new();
-        let mut filename = None;
-        for line in headers.lines() {
-            if line.starts_with("Content-Disposition:") {
-                for attr in line.split(';').skip(1) {
-                    let attr = attr.trim();
-                    if attr.starts_with("name=") {
-                        name = attr
-                            .trim_start_matches("name=\"")
-                            .trim_end_matches("\"")
-                            .to_string();
-                    } else if attr.starts_with("filename=") {
-                        filename = Some(
-                            attr.trim_start_matches("filename=\"")
-                                .trim_end_matches("\"")
-                                .to_string(),
-                        );
+                // Clients may split their cookies across several headers
+                if key.eq_ignore_ascii_case("Cookie") {
+                    if let Some(cookies) = self.headers.get_mut(key) {
+                        cookies.push_str("; ");
+                        cookies.push_str(value);
+                        continue;
                     }
                 }
+                self.headers.insert(key.to_string(), value.to_string());
             }
         }
-        (name, filename)
     }
 }
@@ -0,0 +1,337 @@
+use crate::request::{FromRequest, Request};
+use crate::response::Response;
+
+// Single entry of an `Accept*` header, e.g. `text/html;level=1;q=0.5`
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualityItem {
+    pub value: String,
+    // Parameters other than `q`, names lowercased
+    pub params: Vec<(String, String)>,
+    pub quality: f32,
+}
+
+// Parses a comma separated list of values with optional q-values (RFC 9110, 12.4.2).
+// Items are sorted by quality, entries with the same quality keep their order.
+pub fn parse_quality_list(header: &str) -> Vec<QualityItem> {
+    let mut items: Vec<QualityItem> = header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let value = parts.next()?.trim();
+            if value.is_empty() {
+                return None;
+            }
+            let mut params = Vec::new();
+            let mut quality = 1.0;
+            for param in parts {
+                let Some((name, param_value)) = param.split_once('=') else {
+                    continue;
+                };
+                let name = name.trim().to_ascii_lowercase();
+                let param_value = param_value.trim().trim_matches('"');
+                if name == "q" {
+                    // Items with a malformed q-value are ignored altogether
+                    quality = param_value
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|q| (0.0..=1.0).contains(q))?;
+                } else {
+                    params.push((name, param_value.to_string()));
+                }
+            }
+            Some(QualityItem {
+                value: value.to_string(),
+                params,
+                quality,
+            })
+        })
+        .collect();
+    items.sort_by(|a, b| b.quality.total_cmp(&a.quality));
+    items
+}
+
+// Picks the offer with the highest quality above zero, earlier offers win ties
+pub(crate) fn best_match<T>(
+    offers: impl IntoIterator<Item = T>,
+    quality: impl Fn(&T) -> f32,
+) -> Option<T> {
+    let mut best: Option<(T, f32)> = None;
+    for offer in offers {
+        let offer_quality = quality(&offer);
+        if offer_quality > 0.0 && best.as_ref().is_none_or(|(_, q)| offer_quality > *q) {
+            best = Some((offer, offer_quality));
+        }
+    }
+    best.map(|(offer, _)| offer)
+}
+
+// Quality of the most specific item matching `offer`, `None` if no item matches
+fn matching_quality(
+    items: &[QualityItem],
+    specificity: impl Fn(&QualityItem) -> Option<usize>,
+) -> Option<f32> {
+    items
+        .iter()
+        .filter_map(|item| Some((specificity(item)?, item.quality)))
+        // `max_by_key` returns the last maximum, items are iterated in reverse to get the first one
+        .rev()
+        .max_by_key(|(specificity, _)| *specificity)
+        .map(|(_, quality)| quality)
+}
+
+macro_rules! accept_header {
+    ($name:ident, $header:literal, $blank_is_absent:literal) => {
+        #[derive(Debug, Clone, Default, PartialEq)]
+        pub struct $name {
+            // `None` if the request didn't send the header
+            items: Option<Vec<QualityItem>>,
+        }
+
+        impl $name {
+            pub fn parse(header: Option<&str>) -> $name {
+                let header =
+                    header.filter(|header| !($blank_is_absent && header.trim().is_empty()));
+                $name {
+                    items: header.map(parse_quality_list),
+                }
+            }
+
+            pub fn items(&self) -> &[QualityItem] {
+                self.items.as_deref().unwrap_or_default()
+            }
+
+            pub fn is_present(&self) -> bool {
+                self.items.is_some()
+            }
+
+            // The offer the client prefers, `None` if it accepts none of them
+            pub fn negotiate<'a>(&self, offers: &[&'a str]) -> Option<&'a str> {
+                best_match(offers.iter().copied(), |offer| self.quality(offer))
+            }
+        }
+
+        impl FromRequest for $name {
+            fn from_request(request: &Request) -> Result<Self, Response> {
+                Ok($name::parse(request.header($header)))
+            }
+        }
+    };
+}
+
+// An empty Accept-Encoding header means only `identity` is acceptable,
+// the other headers are treated as if they weren't sent at all
+accept_header!(Accept, "Accept", true);
+accept_header!(AcceptLanguage, "Accept-Language", true);
+accept_header!(AcceptEncoding, "Accept-Encoding", false);
+accept_header!(AcceptCharset, "Accept-Charset", true);
+
+impl Accept {
+    // Quality of a media type like `text/html; charset=utf-8`.
+    // More specific ranges take precedence, i.e. `text/html` over `text/*` over `*/*`.
+    pub fn quality(&self, media_type: &str) -> f32 {
+        let Some(items) = &self.items else {
+            return 1.0;
+        };
+        let mut parts = media_type.split(';');
+        let essence = parts.next().unwrap_or_default().trim();
+        let (main_type, sub_type) = essence.split_once('/').unwrap_or((essence, ""));
+        let params: Vec<(String, String)> = parts
+            .filter_map(|param| {
+                let (name, value) = param.split_once('=')?;
+                Some((
+                    name.trim().to_ascii_lowercase(),
+                    value.trim().trim_matches('"').to_string(),
+                ))
+            })
+            .collect();
+
+        matching_quality(items, |item| {
+            let (range_main, range_sub) = item.value.split_once('/')?;
+            let specificity = if range_main == "*" && range_sub == "*" {
+                0
+            } else if range_main.eq_ignore_ascii_case(main_type) && range_sub == "*" {
+                1
+            } else if range_main.eq_ignore_ascii_case(main_type)
+                && range_sub.eq_ignore_ascii_case(sub_type)
+            {
+                2
+            } else {
+                return None;
+            };
+            // Parameters of the range have to be present on the media type as well
+            let params_match = item.params.iter().all(|(name, value)| {
+                params
+                    .iter()
+                    .any(|(n, v)| n == name && v.eq_ignore_ascii_case(value))
+            });
+            params_match.then_some(specificity + item.params.len())
+        })
+        .unwrap_or(0.0)
+    }
+}
+
+impl AcceptLanguage {
+    // Quality of a language tag like `en-US`. A range matches the tag itself
+    // and every tag it is a prefix of, so `en` matches `en-US` (RFC 4647, 3.3.1).
+    pub fn quality(&self, language: &str) -> f32 {
+        let Some(items) = &self.items else {
+            return 1.0;
+        };
+        let language = language.to_ascii_lowercase();
+        matching_quality(items, |item| {
+            if item.value == "*" {
+                return Some(0);
+            }
+            let range = item.value.to_ascii_lowercase();
+            let matches = language == range
+                || (language.starts_with(&range) && language[range.len()..].starts_with('-'));
+            matches.then(|| range.split('-').count())
+        })
+        .unwrap_or(0.0)
+    }
+}
+
+impl AcceptEncoding {
+    // Quality of a content coding like `gzip`. `identity` is acceptable unless
+    // excluded explicitly, either by itself or through `*` (RFC 9110, 12.5.3).
+    pub fn quality(&self, encoding: &str) -> f32 {
+        let Some(items) = &self.items else {
+            return 1.0;
+        };
+        matching_quality(items, |item| {
+            if item.value == "*" {
+                Some(0)
+            } else {
+                item.value.eq_ignore_ascii_case(encoding).then_some(1)
+            }
+        })
+        .unwrap_or(if encoding.eq_ignore_ascii_case("identity") {
+            1.0
+        } else {
+            0.0
+        })
+    }
+}
+
+impl AcceptCharset {
+    // Quality of a charset like `utf-8`
+    pub fn quality(&self, charset: &str) -> f32 {
+        let Some(items) = &self.items else {
+            return 1.0;
+        };
+        matching_quality(items, |item| {
+            if item.value == "*" {
+                Some(0)
+            } else {
+                item.value.eq_ignore_ascii_case(charset).then_some(1)
+            }
+        })
+        .unwrap_or(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_sorts_by_quality() {
+        let items =
+            parse_quality_list("text/plain;q=0.5, text/html;level=1, */*;q=0.1, ,image/png");
+        let values: Vec<(&str, f32)> = items
+            .iter()
+            .map(|item| (item.value.as_str(), item.quality))
+            .collect();
+        assert_eq!(
+            values,
+            [
+                ("text/html", 1.0),
+                ("image/png", 1.0),
+                ("text/plain", 0.5),
+                ("*/*", 0.1),
+            ]
+        );
+        assert_eq!(items[0].params, [("level".to_string(), "1".to_string())]);
+    }
+
+    #[test]
+    fn ignores_items_with_invalid_quality() {
+        let items = parse_quality_list("a;q=2, b;q=abc, c;Q=0.3, d;q=-1");
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].value, "c");
+        assert_eq!(items[0].quality, 0.3);
+    }
+
+    #[test]
+    fn q_zero_excludes_an_offer() {
+        let accept = Accept::parse(Some("text/html;q=0, */*"));
+        assert_eq!(accept.quality("text/html"), 0.0);
+        assert_eq!(accept.quality("application/json"), 1.0);
+        assert_eq!(
+            accept.negotiate(&["text/html", "application/json"]),
+            Some("application/json")
+        );
+        assert_eq!(accept.negotiate(&["text/html"]), None);
+    }
+
+    #[test]
+    fn more_specific_ranges_take_precedence() {
+        let accept = Accept::parse(Some(
+            "*/*;q=0.1, text/*;q=0.5, text/html;q=0.8, text/html;level=1",
+        ));
+        assert_eq!(accept.quality("image/png"), 0.1);
+        assert_eq!(accept.quality("text/plain"), 0.5);
+        assert_eq!(accept.quality("TEXT/HTML; charset=utf-8"), 0.8);
+        assert_eq!(accept.quality("text/html; level=1"), 1.0);
+    }
+
+    #[test]
+    fn missing_or_blank_header_accepts_everything() {
+        for header in [None, Some(" ")] {
+            let accept = Accept::parse(header);
+            assert!(!accept.is_present());
+            assert_eq!(accept.quality("image/png"), 1.0);
+            assert_eq!(accept.negotiate(&["a/b", "c/d"]), Some("a/b"));
+        }
+    }
+
+    #[test]
+    fn best_match_prefers_earlier_offers_on_ties() {
+        let accept = Accept::parse(Some("application/json, text/html"));
+        assert_eq!(
+            accept.negotiate(&["text/html", "application/json"]),
+            Some("text/html")
+        );
+        assert_eq!(
+            best_match([("a", 0.5), ("b", 0.9), ("c", 0.9)], |(_, q)| *q),
+            Some(("b", 0.9))
+        );
+        assert_eq!(best_match([("a", 0.0)], |(_, q)| *q), None);
+    }
+
+    #[test]
+    fn language_ranges_match_by_prefix() {
+        let accept = AcceptLanguage::parse(Some("de-CH, de;q=0.8, en;q=0.5, *;q=0.1"));
+        assert_eq!(accept.quality("de-CH"), 1.0);
+        assert_eq!(accept.quality("de-DE"), 0.8);
+        assert_eq!(accept.quality("EN-us"), 0.5);
+        assert_eq!(accept.quality("enx"), 0.1);
+        assert_eq!(accept.negotiate(&["en", "de-AT"]), Some("de-AT"));
+    }
+
+    #[test]
+    fn identity_encoding_is_acceptable_unless_excluded() {
+        let accept = AcceptEncoding::parse(Some("gzip;q=0.5, br"));
+        assert_eq!(accept.quality("br"), 1.0);
+        assert_eq!(accept.quality("deflate"), 0.0);
+        assert_eq!(accept.quality("identity"), 1.0);
+
+        let accept = AcceptEncoding::parse(Some("gzip, *;q=0"));
+        assert_eq!(accept.quality("identity"), 0.0);
+
+        // An empty header only allows identity
+        let accept = AcceptEncoding::parse(Some(""));
+        assert!(accept.is_present());
+        assert_eq!(accept.negotiate(&["gzip", "identity"]), Some("identity"));
+    }
+}
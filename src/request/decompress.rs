@@ -0,0 +1,208 @@
+use crate::encoding::Encoding;
+use crate::request::ParseError;
+use flate2::read::{MultiGzDecoder, ZlibDecoder};
+use std::io::Read;
+
+// Encodings listed in a `Content-Encoding` header, in the order they were applied
+pub(crate) fn parse_encodings(content_encoding: &str) -> Result<Vec<Encoding>, ParseError> {
+    content_encoding
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty() && !name.eq_ignore_ascii_case("identity"))
+        .map(|name| {
+            Encoding::from_name(name)
+                .ok_or_else(|| ParseError::UnsupportedEncoding(name.to_string()))
+        })
+        .collect()
+}
+
+// Undoes `encodings` from last to first. Decoding stops as soon as the output grows past `limit`,
+// so a small compressed body can't expand into gigabytes (a "zip bomb").
+pub(crate) fn decompress(
+    mut body: Vec<u8>,
+    encodings: &[Encoding],
+    limit: usize,
+) -> Result<Vec<u8>, ParseError> {
+    for encoding in encodings.iter().rev() {
+        let mut decoded = Vec::new();
+        {
+            let decoder: Box<dyn Read + '_> = match encoding {
+                Encoding::Brotli => Box::new(brotli::Decompressor::new(&body[..], 4096)),
+                // Gzip bodies may consist of several members, which are simply concatenated
+                Encoding::Gzip => Box::new(MultiGzDecoder::new(&body[..])),
+                Encoding::Deflate => Box::new(ZlibDecoder::new(&body[..])),
+            };
+            decoder
+                .take(limit as u64 + 1)
+                .read_to_end(&mut decoded)
+                .map_err(|_| ParseError::Malformed)?;
+        }
+        if decoded.len() > limit {
+            return Err(ParseError::BodyTooLarge);
+        }
+        body = decoded;
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::{Request, RequestConfig};
+    use crate::response::StatusCode;
+    use async_std::io::WriteExt;
+    use async_std::net::{TcpListener, TcpStream};
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn brotli(data: &[u8]) -> Vec<u8> {
+        let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+        encoder.write_all(data).unwrap();
+        encoder.into_inner()
+    }
+
+    // Parses a request with a compressed body sent over a real connection
+    async fn parse(
+        content_encoding: &str,
+        body: &[u8],
+        config: &RequestConfig,
+    ) -> Result<Request, ParseError> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let head = format!(
+            "POST /upload HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Encoding: {}\r\nContent-Length: {}\r\n\r\n",
+            content_encoding,
+            body.len()
+        );
+        client.write_all(head.as_bytes()).await.unwrap();
+        client.write_all(body).await.unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Request::new();
+        request.parse_with_config(&mut stream, config).await?;
+        Ok(request)
+    }
+
+    #[test]
+    fn parses_encodings_in_order() {
+        assert_eq!(
+            parse_encodings("deflate, identity, X-Gzip").unwrap(),
+            [Encoding::Deflate, Encoding::Gzip]
+        );
+        assert!(parse_encodings("identity").unwrap().is_empty());
+        assert!(matches!(
+            parse_encodings("gzip, compress"),
+            Err(ParseError::UnsupportedEncoding(name)) if name == "compress"
+        ));
+    }
+
+    #[test]
+    fn decodes_each_encoding() {
+        let text = b"hello world ".repeat(100);
+        assert_eq!(
+            decompress(gzip(&text), &[Encoding::Gzip], 4096).unwrap(),
+            text
+        );
+        assert_eq!(
+            decompress(deflate(&text), &[Encoding::Deflate], 4096).unwrap(),
+            text
+        );
+        assert_eq!(
+            decompress(brotli(&text), &[Encoding::Brotli], 4096).unwrap(),
+            text
+        );
+
+        // Applied as deflate first, then gzip
+        let layered = gzip(&deflate(&text));
+        let encodings = [Encoding::Deflate, Encoding::Gzip];
+        assert_eq!(decompress(layered, &encodings, 4096).unwrap(), text);
+
+        let mut members = gzip(b"hello ");
+        members.extend(gzip(b"world"));
+        assert_eq!(
+            decompress(members, &[Encoding::Gzip], 4096).unwrap(),
+            b"hello world"
+        );
+    }
+
+    #[test]
+    fn rejects_corrupt_bodies() {
+        let mut corrupt = gzip(&b"hello world ".repeat(100));
+        corrupt.truncate(corrupt.len() / 2);
+        assert!(matches!(
+            decompress(corrupt, &[Encoding::Gzip], 4096),
+            Err(ParseError::Malformed)
+        ));
+        assert!(matches!(
+            decompress(b"not deflate".to_vec(), &[Encoding::Deflate], 4096),
+            Err(ParseError::Malformed)
+        ));
+    }
+
+    #[test]
+    fn stops_inflating_at_the_limit() {
+        // 4096 members of a megabyte of zeros each, 4 GB in total. Decoding it all would take
+        // far longer than the test, so the limit has to stop it right away.
+        let member = gzip(&vec![0; 1024 * 1024]);
+        let bomb = member.repeat(4096);
+        assert!(bomb.len() < 8 * 1024 * 1024);
+        assert!(matches!(
+            decompress(bomb, &[Encoding::Gzip], 1024 * 1024),
+            Err(ParseError::BodyTooLarge)
+        ));
+
+        let exact = vec![0; 1024];
+        assert_eq!(
+            decompress(gzip(&exact), &[Encoding::Gzip], 1024).unwrap(),
+            exact
+        );
+        assert!(matches!(
+            decompress(gzip(&[0; 1025]), &[Encoding::Gzip], 1024),
+            Err(ParseError::BodyTooLarge)
+        ));
+    }
+
+    #[async_std::test]
+    async fn request_sees_the_decompressed_body() {
+        let text = b"hello world ".repeat(100);
+        let request = parse("gzip", &gzip(&text), &RequestConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(request.body, text);
+        assert_eq!(request.header("Content-Encoding"), None);
+        assert_eq!(request.header("Content-Length"), Some("1200"));
+    }
+
+    #[async_std::test]
+    async fn rejects_unknown_encoding_with_415() {
+        let err = parse("compress", b"data", &RequestConfig::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(err.to_string(), "unsupported content encoding 'compress'");
+    }
+
+    #[async_std::test]
+    async fn rejects_inflating_past_the_limit_with_413() {
+        let config = RequestConfig {
+            max_decompressed_size: 1024,
+            ..RequestConfig::default()
+        };
+        let err = parse("br", &brotli(&[0; 4096]), &config).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,714 @@
+use crate::headers;
+use crate::request::{FromRequest, Request};
+use crate::response::{Response, StatusCode};
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+// URL-encoded form body (`application/x-www-form-urlencoded`) deserialized into `T`.
+// Repeated keys (`tag=a&tag=b`) and `tag[]=a` fill a `Vec`, bracket keys like
+// `user[name]=x` fill nested structs and `items[0][id]=1` fills a `Vec` of structs.
+#[derive(Debug, Clone)]
+pub struct Form<T>(pub T);
+
+// Query string of the request path deserialized into `T`, same rules as `Form`.
+#[derive(Debug, Clone)]
+pub struct Query<T>(pub T);
+
+// Limits on what a request can make the parser build
+const MAX_FIELDS: usize = 1000;
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug)]
+pub enum FormRejection {
+    UnsupportedMediaType {
+        content_type: Option<String>,
+    },
+    Deserialize {
+        path: Option<String>,
+        message: String,
+    },
+    // More fields or deeper nesting than the parser accepts
+    TooComplex {
+        reason: String,
+    },
+}
+
+pub fn from_str<T: DeserializeOwned>(input: &str) -> Result<T, FormRejection> {
+    from_bytes(input.as_bytes())
+}
+
+pub fn from_bytes<T: DeserializeOwned>(input: &[u8]) -> Result<T, FormRejection> {
+    let mut root = Fields::default();
+    for (index, (key, value)) in form_urlencoded::parse(input).enumerate() {
+        if index == MAX_FIELDS {
+            return Err(FormRejection::TooComplex {
+                reason: format!("more than {} fields", MAX_FIELDS),
+            });
+        }
+        let (name, segments) = split_key(&key);
+        if segments.len() > MAX_DEPTH {
+            return Err(FormRejection::TooComplex {
+                reason: format!("a key is nested more than {} levels deep", MAX_DEPTH),
+            });
+        }
+        insert(&mut root, name, &segments, value.into_owned());
+    }
+    serde_path_to_error::deserialize(NodeDeserializer(Node::Map(root))).map_err(|err| {
+        let path = err.path().to_string();
+        FormRejection::Deserialize {
+            path: if path == "." { None } else { Some(path) },
+            message: err.into_inner().0,
+        }
+    })
+}
+
+impl<T: DeserializeOwned> Form<T> {
+    pub fn from_request_checked(request: &Request) -> Result<Self, FormRejection> {
+        let content_type = request.header("Content-Type");
+        let is_form = content_type.is_some_and(|content_type| {
+            content_type
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .eq_ignore_ascii_case("application/x-www-form-urlencoded")
+        });
+        if !is_form {
+            return Err(FormRejection::UnsupportedMediaType {
+                content_type: content_type.map(str::to_string),
+            });
+        }
+        from_bytes(&request.body).map(Form)
+    }
+}
+
+impl<T: DeserializeOwned> Query<T> {
+    pub fn from_request_checked(request: &Request) -> Result<Self, FormRejection> {
+        from_str(request.query().unwrap_or_default()).map(Query)
+    }
+}
+
+impl<T: DeserializeOwned> FromRequest for Form<T> {
+    fn from_request(request: &Request) -> Result<Self, Response> {
+        Self::from_request_checked(request)
+            .map_err(|rejection| rejection.into_response(StatusCode::UNPROCESSABLE_ENTITY))
+    }
+}
+
+impl<T: DeserializeOwned> FromRequest for Query<T> {
+    fn from_request(request: &Request) -> Result<Self, Response> {
+        Self::from_request_checked(request)
+            .map_err(|rejection| rejection.into_response(StatusCode::BAD_REQUEST))
+    }
+}
+
+impl<T> Form<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Query<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Form<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Form<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T> Deref for Query<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Query<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl FormRejection {
+    fn into_response(self, invalid_status: StatusCode) -> Response {
+        let status_code = match self {
+            FormRejection::UnsupportedMediaType { .. } => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            FormRejection::Deserialize { .. } => invalid_status,
+            FormRejection::TooComplex { .. } => StatusCode::BAD_REQUEST,
+        };
+        let mut body = json!({ "error": self.to_string() });
+        if let FormRejection::Deserialize {
+            path: Some(path), ..
+        } = &self
+        {
+            body["path"] = json!(path);
+        }
+        let body = serde_json::to_vec(&body).unwrap();
+        Response::new(status_code)
+            .with_headers(headers!(("Content-Type", "application/json")))
+            .with_body(body)
+    }
+}
+
+impl fmt::Display for FormRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormRejection::UnsupportedMediaType { content_type } => write!(
+                f,
+                "expected Content-Type 'application/x-www-form-urlencoded', got '{}'",
+                content_type.as_deref().unwrap_or("none")
+            ),
+            FormRejection::Deserialize {
+                path: Some(path),
+                message,
+            } => write!(f, "invalid value for '{}': {}", path, message),
+            FormRejection::Deserialize {
+                path: None,
+                message,
+            } => write!(f, "invalid form data: {}", message),
+            FormRejection::TooComplex { reason } => {
+                write!(f, "form data is too complex: {}", reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FormRejection {}
+
+// Intermediate tree the flat key/value pairs are folded into before deserializing
+#[derive(Debug)]
+enum Node {
+    Leaf(String),
+    Seq(Vec<Node>),
+    Map(Fields),
+}
+
+// Fields in the order they were first given, indexed by name
+#[derive(Debug, Default)]
+struct Fields {
+    entries: Vec<(String, Node)>,
+    positions: HashMap<String, usize>,
+}
+
+impl Fields {
+    fn contains(&self, name: &str) -> bool {
+        self.positions.contains_key(name)
+    }
+
+    fn push(&mut self, name: &str, node: Node) -> &mut Node {
+        self.positions.insert(name.to_string(), self.entries.len());
+        self.entries.push((name.to_string(), node));
+        &mut self.entries.last_mut().unwrap().1
+    }
+}
+
+// Splits `user[address][zip]` into `user` and `["address", "zip"]`.
+// Keys with unbalanced brackets are taken literally.
+fn split_key(key: &str) -> (&str, Vec<&str>) {
+    let Some(open) = key.find('[') else {
+        return (key, Vec::new());
+    };
+    if open == 0 {
+        return (key, Vec::new());
+    }
+    let mut segments = Vec::new();
+    let mut rest = &key[open..];
+    while let Some(stripped) = rest.strip_prefix('[') {
+        match stripped.find(']') {
+            Some(close) => {
+                segments.push(&stripped[..close]);
+                rest = &stripped[close + 1..];
+            }
+            None => return (key, Vec::new()),
+        }
+    }
+    if !rest.is_empty() {
+        return (key, Vec::new());
+    }
+    (&key[..open], segments)
+}
+
+// Recurses once per segment, so callers have to limit the number of segments
+fn insert(fields: &mut Fields, key: &str, segments: &[&str], value: String) {
+    let node = match fields.positions.get(key).copied() {
+        Some(index) => &mut fields.entries[index].1,
+        None => {
+            let node = match segments.first() {
+                None => {
+                    fields.push(key, Node::Leaf(value));
+                    return;
+                }
+                Some(&"") => Node::Seq(Vec::new()),
+                Some(_) => Node::Map(Fields::default()),
+            };
+            fields.push(key, node)
+        }
+    };
+
+    match segments.split_first() {
+        // Repeated plain key: collect all values
+        None => match node {
+            Node::Seq(items) => items.push(Node::Leaf(value)),
+            Node::Leaf(_) => {
+                let previous = std::mem::replace(node, Node::Seq(Vec::new()));
+                if let Node::Seq(items) = node {
+                    items.push(previous);
+                    items.push(Node::Leaf(value));
+                }
+            }
+            Node::Map(_) => {}
+        },
+        // `key[]` appends to a sequence, `key[][field]` fills the last element
+        Some((&"", rest)) => {
+            if let Node::Leaf(_) = node {
+                let previous = std::mem::replace(node, Node::Seq(Vec::new()));
+                if let Node::Seq(items) = node {
+                    items.push(previous);
+                }
+            }
+            let Node::Seq(items) = node else {
+                return;
+            };
+            match rest.split_first() {
+                None => items.push(Node::Leaf(value)),
+                Some((field, rest)) => {
+                    let append = match items.last() {
+                        Some(Node::Map(fields)) => fields.contains(field),
+                        _ => true,
+                    };
+                    if append {
+                        items.push(Node::Map(Fields::default()));
+                    }
+                    if let Some(Node::Map(fields)) = items.last_mut() {
+                        insert(fields, field, rest, value);
+                    }
+                }
+            }
+        }
+        Some((field, rest)) => {
+            if let Node::Map(fields) = node {
+                insert(fields, field, rest, value);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+struct NodeDeserializer(Node);
+
+impl NodeDeserializer {
+    // Scalars take the last value if a key was given more than once
+    fn into_leaf(self) -> Result<String, Error> {
+        match self.0 {
+            Node::Leaf(value) => Ok(value),
+            Node::Seq(mut items) => match items.pop() {
+                Some(item) => NodeDeserializer(item).into_leaf(),
+                None => Err(de::Error::custom("expected a value, found an empty list")),
+            },
+            Node::Map(_) => Err(de::Error::custom("expected a value, found nested fields")),
+        }
+    }
+
+    fn into_items(self) -> Result<Vec<Node>, Error> {
+        match self.0 {
+            Node::Seq(items) => Ok(items),
+            Node::Leaf(value) => Ok(vec![Node::Leaf(value)]),
+            // `items[0]=a&items[1]=b`
+            Node::Map(fields) => {
+                let mut indexed = fields
+                    .entries
+                    .into_iter()
+                    .map(|(key, node)| key.parse::<usize>().map(|index| (index, node)))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| de::Error::custom("expected a list, found nested fields"))?;
+                indexed.sort_by_key(|(index, _)| *index);
+                Ok(indexed.into_iter().map(|(_, node)| node).collect())
+            }
+        }
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                let value = self.into_leaf()?;
+                match value.trim().parse() {
+                    Ok(parsed) => visitor.$visit(parsed),
+                    Err(err) => Err(de::Error::custom(format!("'{}': {}", value, err))),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for NodeDeserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Node::Leaf(value) => visitor.visit_string(value),
+            Node::Seq(items) => visitor.visit_seq(SeqAccess(items.into_iter())),
+            Node::Map(fields) => visitor.visit_map(MapAccess {
+                fields: fields.entries.into_iter(),
+                value: None,
+            }),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    // Checkboxes are submitted as `on` when checked
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let value = self.into_leaf()?;
+        match value.to_ascii_lowercase().as_str() {
+            "true" | "on" | "1" => visitor.visit_bool(true),
+            "false" | "off" | "0" => visitor.visit_bool(false),
+            _ => Err(de::Error::custom(format!("'{}' is not a boolean", value))),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_string(self.into_leaf()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_string(self.into_leaf()?)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_byte_buf(self.into_leaf()?.into_bytes())
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_byte_buf(self.into_leaf()?.into_bytes())
+    }
+
+    // Empty fields are treated as missing
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match &self.0 {
+            Node::Leaf(value) if value.is_empty() => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(SeqAccess(self.into_items()?.into_iter()))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Node::Map(fields) => visitor.visit_map(MapAccess {
+                fields: fields.entries.into_iter(),
+                value: None,
+            }),
+            _ => Err(de::Error::custom("expected nested fields, found a value")),
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_enum(self.into_leaf()?.into_deserializer())
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+}
+
+struct SeqAccess(std::vec::IntoIter<Node>);
+
+impl<'de> de::SeqAccess<'de> for SeqAccess {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        match self.0.next() {
+            Some(node) => seed.deserialize(NodeDeserializer(node)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+struct MapAccess {
+    fields: std::vec::IntoIter<(String, Node)>,
+    value: Option<Node>,
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        match self.fields.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(NodeDeserializer(Node::Leaf(key)))
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        match self.value.take() {
+            Some(node) => seed.deserialize(NodeDeserializer(node)),
+            None => Err(de::Error::custom("value is missing")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Address {
+        city: String,
+        zip: u32,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct User {
+        name: String,
+        address: Address,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Item {
+        id: u32,
+        label: Option<String>,
+    }
+
+    #[test]
+    fn decodes_plus_and_percent_escapes() {
+        let values: HashMap<String, String> =
+            from_str("name=Jane+Doe&city=S%C3%A3o%20Paulo&sum=1%2B1").unwrap();
+        assert_eq!(values["name"], "Jane Doe");
+        assert_eq!(values["city"], "São Paulo");
+        assert_eq!(values["sum"], "1+1");
+    }
+
+    #[test]
+    fn collects_repeated_keys() {
+        let values: HashMap<String, Vec<String>> = from_str("tag=a&tag=b&tag=c&one=x").unwrap();
+        assert_eq!(values["tag"], ["a", "b", "c"]);
+        assert_eq!(values["one"], ["x"]);
+
+        // Scalars take the last value
+        let values: HashMap<String, u32> = from_str("page=1&page=2").unwrap();
+        assert_eq!(values["page"], 2);
+    }
+
+    #[test]
+    fn fills_nested_maps() {
+        let user: HashMap<String, User> =
+            from_str("user[name]=Jane&user[address][city]=Berlin&user[address][zip]=10115")
+                .unwrap();
+        assert_eq!(
+            user["user"],
+            User {
+                name: "Jane".to_string(),
+                address: Address {
+                    city: "Berlin".to_string(),
+                    zip: 10115,
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn fills_sequences() {
+        let values: HashMap<String, Vec<String>> = from_str("tag[]=a&tag[]=b").unwrap();
+        assert_eq!(values["tag"], ["a", "b"]);
+
+        let values: HashMap<String, Vec<Item>> =
+            from_str("items[][id]=1&items[][label]=first&items[][id]=2").unwrap();
+        assert_eq!(
+            values["items"],
+            [
+                Item {
+                    id: 1,
+                    label: Some("first".to_string()),
+                },
+                Item { id: 2, label: None },
+            ]
+        );
+
+        let values: HashMap<String, Vec<Item>> =
+            from_str("items[1][id]=2&items[0][id]=1&items[0][label]=").unwrap();
+        assert_eq!(
+            values["items"],
+            [Item { id: 1, label: None }, Item { id: 2, label: None }]
+        );
+    }
+
+    #[test]
+    fn reports_the_path_of_invalid_values() {
+        let error = from_str::<HashMap<String, User>>(
+            "user[name]=Jane&user[address][city]=Berlin&user[address][zip]=abc",
+        )
+        .unwrap_err();
+        let FormRejection::Deserialize { path, .. } = &error else {
+            panic!("unexpected rejection: {}", error);
+        };
+        assert_eq!(path.as_deref(), Some("user.address.zip"));
+    }
+
+    #[test]
+    fn rejects_deeply_nested_keys() {
+        let key = format!("a{}", "[x]".repeat(MAX_DEPTH));
+        assert!(from_str::<serde_json::Value>(&format!("{}=1", key)).is_ok());
+
+        // Would overflow the stack without the limit
+        let key = format!("a{}", "[x]".repeat(20_000));
+        let error = from_str::<serde_json::Value>(&format!("{}=1", key)).unwrap_err();
+        assert!(matches!(error, FormRejection::TooComplex { .. }));
+        assert_eq!(
+            error.to_string(),
+            "form data is too complex: a key is nested more than 32 levels deep"
+        );
+
+        let mut request = Request::new();
+        request.path = format!("/search?{}=1", key);
+        let error = Query::<serde_json::Value>::from_request_checked(&request).unwrap_err();
+        assert!(matches!(error, FormRejection::TooComplex { .. }));
+    }
+
+    #[test]
+    fn rejects_too_many_fields() {
+        let input = (0..MAX_FIELDS)
+            .map(|index| format!("field{}=1", index))
+            .collect::<Vec<_>>()
+            .join("&");
+        let values: HashMap<String, u32> = from_str(&input).unwrap();
+        assert_eq!(values.len(), MAX_FIELDS);
+
+        let error = from_str::<HashMap<String, u32>>(&format!("{}&one=more", input)).unwrap_err();
+        assert!(matches!(error, FormRejection::TooComplex { .. }));
+    }
+
+    #[test]
+    fn form_requires_the_content_type() {
+        let mut request = Request::new();
+        request.body = b"name=Jane".to_vec();
+        let error = Form::<HashMap<String, String>>::from_request_checked(&request).unwrap_err();
+        assert!(matches!(error, FormRejection::UnsupportedMediaType { .. }));
+
+        request.headers.insert(
+            "Content-Type".to_string(),
+            "Application/X-WWW-Form-Urlencoded; charset=UTF-8".to_string(),
+        );
+        let form = Form::<HashMap<String, String>>::from_request_checked(&request).unwrap();
+        assert_eq!(form["name"], "Jane");
+    }
+}
@@ -0,0 +1,349 @@
+use crate::headers;
+use crate::request::{FromRequest, Request};
+use crate::response::{Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde_json::error::Category;
+use serde_json::json;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+pub const DEFAULT_JSON_LIMIT: usize = 2 * 1024 * 1024;
+
+// JSON request body deserialized into `T`.
+//
+// Usage in a handler:
+//   let Json(user) = match Json::<User>::from_request(&request) {
+//       Ok(json) => json,
+//       Err(response) => return response,
+//   };
+#[derive(Debug, Clone)]
+pub struct Json<T>(pub T);
+
+#[derive(Debug)]
+pub enum JsonRejection {
+    UnsupportedMediaType {
+        content_type: Option<String>,
+    },
+    PayloadTooLarge {
+        limit: usize,
+    },
+    Syntax {
+        message: String,
+        line: usize,
+        column: usize,
+    },
+    Data {
+        path: Option<String>,
+        message: String,
+        line: usize,
+        column: usize,
+    },
+}
+
+impl<T: DeserializeOwned> Json<T> {
+    pub fn from_request_with_limit(request: &Request, limit: usize) -> Result<Self, JsonRejection> {
+        let content_type = request.header("Content-Type");
+        if !content_type.is_some_and(is_json_content_type) {
+            return Err(JsonRejection::UnsupportedMediaType {
+                content_type: content_type.map(str::to_string),
+            });
+        }
+        if request.body.len() > limit {
+            return Err(JsonRejection::PayloadTooLarge { limit });
+        }
+        Self::from_slice(&request.body)
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, JsonRejection> {
+        let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+        let value = serde_path_to_error::deserialize(&mut deserializer).map_err(|err| {
+            let path = err.path().to_string();
+            let path = if path == "." { None } else { Some(path) };
+            JsonRejection::from_serde(err.into_inner(), path)
+        })?;
+        // Reject trailing characters after the JSON value
+        deserializer
+            .end()
+            .map_err(|err| JsonRejection::from_serde(err, None))?;
+        Ok(Json(value))
+    }
+}
+
+impl<T> Json<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Json<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Json<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: DeserializeOwned> FromRequest for Json<T> {
+    fn from_request(request: &Request) -> Result<Self, Response> {
+        Self::from_request_with_limit(request, DEFAULT_JSON_LIMIT).map_err(Response::from)
+    }
+}
+
+// Accepts `application/json` as well as structured syntax suffixes like `application/problem+json`
+fn is_json_content_type(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    match mime.split_once('/') {
+        Some((kind, subtype)) => {
+            kind == "application" && (subtype == "json" || subtype.ends_with("+json"))
+        }
+        None => false,
+    }
+}
+
+impl JsonRejection {
+    fn from_serde(err: serde_json::Error, path: Option<String>) -> JsonRejection {
+        let (line, column) = (err.line(), err.column());
+        // serde_json appends " at line X column Y" to its messages, we report those separately
+        let message = err.to_string();
+        let message = match message.rfind(" at line ") {
+            Some(index) => message[..index].to_string(),
+            None => message,
+        };
+        match err.classify() {
+            Category::Data => JsonRejection::Data {
+                path,
+                message,
+                line,
+                column,
+            },
+            Category::Syntax | Category::Eof | Category::Io => JsonRejection::Syntax {
+                message,
+                line,
+                column,
+            },
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            JsonRejection::UnsupportedMediaType { .. } => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            JsonRejection::PayloadTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            JsonRejection::Syntax { .. } => StatusCode::BAD_REQUEST,
+            JsonRejection::Data { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+impl fmt::Display for JsonRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonRejection::UnsupportedMediaType { content_type } => write!(
+                f,
+                "expected Content-Type 'application/json', got '{}'",
+                content_type.as_deref().unwrap_or("none")
+            ),
+            JsonRejection::PayloadTooLarge { limit } => {
+                write!(f, "request body exceeds the limit of {} bytes", limit)
+            }
+            JsonRejection::Syntax {
+                message,
+                line,
+                column,
+            } => write!(
+                f,
+                "invalid JSON at line {} column {}: {}",
+                line, column, message
+            ),
+            JsonRejection::Data {
+                path: Some(path),
+                message,
+                line,
+                column,
+            } => write!(
+                f,
+                "invalid value for '{}' at line {} column {}: {}",
+                path, line, column, message
+            ),
+            JsonRejection::Data {
+                path: None,
+                message,
+                line,
+                column,
+            } => write!(
+                f,
+                "invalid value at line {} column {}: {}",
+                line, column, message
+            ),
+        }
+    }
+}
+
+impl std::error::Error for JsonRejection {}
+
+impl From<JsonRejection> for Response {
+    fn from(rejection: JsonRejection) -> Response {
+        let mut body = json!({ "error": rejection.to_string() });
+        match &rejection {
+            JsonRejection::Syntax { line, column, .. } => {
+                body["line"] = json!(line);
+                body["column"] = json!(column);
+            }
+            JsonRejection::Data {
+                path, line, column, ..
+            } => {
+                body["path"] = json!(path);
+                body["line"] = json!(line);
+                body["column"] = json!(column);
+            }
+            _ => {}
+        }
+        let body = serde_json::to_vec(&body).unwrap();
+        Response::new(rejection.status_code())
+            .with_headers(headers!(("Content-Type", "application/json")))
+            .with_body(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct User {
+        name: String,
+        age: u8,
+        tags: Vec<String>,
+    }
+
+    fn request(content_type: Option<&str>, body: &str) -> Request {
+        let mut request = Request::new();
+        if let Some(content_type) = content_type {
+            request
+                .headers
+                .insert("Content-Type".to_string(), content_type.to_string());
+        }
+        request.body = body.as_bytes().to_vec();
+        request
+    }
+
+    fn rejection_message(body: &[u8]) -> String {
+        Json::<User>::from_slice(body).unwrap_err().to_string()
+    }
+
+    const USER: &str = r#"{"name": "Ada", "age": 36, "tags": ["admin"]}"#;
+
+    #[test]
+    fn accepts_json_and_json_suffixes() {
+        for content_type in [
+            "application/json",
+            "Application/JSON; charset=utf-8",
+            "application/problem+json",
+            "application/vnd.api+json ; charset=UTF-8",
+        ] {
+            let Json(user) =
+                Json::<User>::from_request_with_limit(&request(Some(content_type), USER), 1024)
+                    .unwrap();
+            assert_eq!(user.name, "Ada", "{}", content_type);
+        }
+    }
+
+    #[test]
+    fn rejects_missing_or_wrong_content_type() {
+        for content_type in [
+            None,
+            Some("text/plain"),
+            Some("text/json"),
+            Some("application/jsonp"),
+            Some("application/json+xml"),
+        ] {
+            let rejection =
+                Json::<User>::from_request_with_limit(&request(content_type, USER), 1024)
+                    .unwrap_err();
+            assert!(
+                matches!(rejection, JsonRejection::UnsupportedMediaType { .. }),
+                "{:?}",
+                content_type
+            );
+            assert_eq!(rejection.status_code(), 415);
+        }
+        let rejection = Json::<User>::from_request(&request(None, USER)).unwrap_err();
+        assert_eq!(rejection.status_code, 415);
+        assert_eq!(
+            rejection.headers.get("Content-Type").map(String::as_str),
+            Some("application/json")
+        );
+    }
+
+    #[test]
+    fn rejects_bodies_over_the_limit() {
+        let rejection =
+            Json::<User>::from_request_with_limit(&request(Some("application/json"), USER), 16)
+                .unwrap_err();
+        assert_eq!(rejection.status_code(), 413);
+        assert_eq!(
+            rejection.to_string(),
+            "request body exceeds the limit of 16 bytes"
+        );
+        assert!(Json::<User>::from_request_with_limit(
+            &request(Some("application/json"), USER),
+            USER.len()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn reports_syntax_errors_with_position() {
+        let rejection =
+            Json::<User>::from_slice(b"{\n  \"name\": \"Ada\",\n  age: 36\n}").unwrap_err();
+        assert_eq!(rejection.status_code(), 400);
+        let JsonRejection::Syntax { line, column, .. } = rejection else {
+            panic!("expected a syntax error, got {:?}", rejection);
+        };
+        assert_eq!((line, column), (3, 3));
+        assert!(rejection_message(b"{\"name\": ").starts_with("invalid JSON at line 1 column 9"));
+        // Trailing characters after the value
+        assert!(matches!(
+            Json::<User>::from_slice(format!("{} x", USER).as_bytes()),
+            Err(JsonRejection::Syntax { .. })
+        ));
+    }
+
+    #[test]
+    fn reports_field_path_of_type_mismatches() {
+        let rejection =
+            Json::<User>::from_slice(br#"{"name": "Ada", "age": 36, "tags": ["admin", 7]}"#)
+                .unwrap_err();
+        assert_eq!(rejection.status_code(), 422);
+        let JsonRejection::Data { path, line, .. } = &rejection else {
+            panic!("expected a data error, got {:?}", rejection);
+        };
+        assert_eq!(path.as_deref(), Some("tags[1]"));
+        assert_eq!(*line, 1);
+        assert!(rejection
+            .to_string()
+            .starts_with("invalid value for 'tags[1]' at line 1 column 46"));
+
+        let rejection =
+            Json::<User>::from_slice(br#"{"name": "Ada", "age": 300, "tags": []}"#).unwrap_err();
+        assert!(matches!(
+            rejection,
+            JsonRejection::Data { path: Some(path), .. } if path == "age"
+        ));
+        // Missing fields are reported for the object containing them
+        let rejection = Json::<User>::from_slice(br#"{"name": "Ada"}"#).unwrap_err();
+        assert!(matches!(rejection, JsonRejection::Data { path: None, .. }));
+        assert_eq!(Response::from(rejection).status_code, 422);
+    }
+}
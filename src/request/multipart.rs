@@ -0,0 +1,525 @@
+use crate::request::{FormData, RequestConfig};
+use crate::utils::{get_content_type, sniff_content_type};
+use async_std::fs::{self, File, OpenOptions};
+use async_std::io::{self, Read, ReadExt, WriteExt};
+use async_std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+const MAX_PART_HEADER_SIZE: usize = 8 * 1024;
+
+// Number of leading bytes kept around to detect the content type of spooled files
+const SNIFF_LENGTH: usize = 16;
+
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// File part of a multipart upload that has been written to a temporary file.
+// The file is removed when this is dropped (i.e. once the request is done),
+// unless it has been moved somewhere else with `persist`.
+#[derive(Debug)]
+pub struct UploadedFile {
+    path: PathBuf,
+    size: u64,
+    content_type: String,
+    persisted: bool,
+}
+
+impl UploadedFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    // Detected from the file's leading bytes, falling back to the type sent by the client
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    // Moves the upload to `path` and keeps it from being cleaned up
+    pub async fn persist(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        // Renaming fails across file systems, copy the file in that case
+        if fs::rename(&self.path, path).await.is_err() {
+            fs::copy(&self.path, path).await?;
+            let _ = fs::remove_file(&self.path).await;
+        }
+        self.path = path.to_path_buf();
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+// Removing the file blocks, but only for as long as unlinking a single file takes,
+// which isn't worth handing off to another thread
+impl Drop for UploadedFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+struct TempFile {
+    file: File,
+    upload: UploadedFile,
+}
+
+impl TempFile {
+    async fn create(dir: &Path) -> io::Result<TempFile> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos())
+            .unwrap_or_default();
+        let counter = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!(
+            "surfer-upload-{}-{}-{}",
+            std::process::id(),
+            nanos,
+            counter
+        ));
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await?;
+        Ok(TempFile {
+            file,
+            upload: UploadedFile {
+                path,
+                size: 0,
+                content_type: String::new(),
+                persisted: false,
+            },
+        })
+    }
+
+    async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.file.write_all(data).await?;
+        self.upload.size += data.len() as u64;
+        Ok(())
+    }
+}
+
+enum Sink {
+    Memory(Vec<u8>),
+    File(TempFile),
+}
+
+struct Part {
+    name: String,
+    filename: Option<String>,
+    content_type: Option<String>,
+    head: Vec<u8>,
+    sink: Sink,
+    size: usize,
+}
+
+impl Part {
+    // `previous` is the size of the parts before this one
+    async fn write(
+        &mut self,
+        data: &[u8],
+        previous: usize,
+        config: &RequestConfig,
+    ) -> io::Result<()> {
+        self.size += data.len();
+        if config.max_part_size.is_some_and(|max| self.size > max) {
+            return Err(invalid_data("multipart part is too large"));
+        }
+        if config
+            .max_multipart_size
+            .is_some_and(|max| previous + self.size > max)
+        {
+            return Err(invalid_data("multipart body is too large"));
+        }
+        if self.head.len() < SNIFF_LENGTH {
+            let missing = (SNIFF_LENGTH - self.head.len()).min(data.len());
+            self.head.extend_from_slice(&data[..missing]);
+        }
+        match &mut self.sink {
+            Sink::File(file) => file.write(data).await,
+            Sink::Memory(buffer) => {
+                let spool = match config.spool_threshold {
+                    Some(threshold) => {
+                        self.filename.is_some() && buffer.len() + data.len() > threshold
+                    }
+                    None => false,
+                };
+                if !spool {
+                    buffer.extend_from_slice(data);
+                    return Ok(());
+                }
+                let mut file = TempFile::create(&config.upload_dir).await?;
+                file.write(buffer).await?;
+                file.write(data).await?;
+                self.sink = Sink::File(file);
+                Ok(())
+            }
+        }
+    }
+
+    async fn finish(self) -> io::Result<FormData> {
+        let (data, file) = match self.sink {
+            Sink::Memory(data) => (data, None),
+            Sink::File(mut file) => {
+                file.file.flush().await?;
+                file.upload.content_type = sniff_content_type(&self.head)
+                    .map(str::to_string)
+                    .or_else(|| self.content_type.clone())
+                    .unwrap_or_else(|| {
+                        let filename = self.filename.as_deref().unwrap_or_default();
+                        get_content_type(&PathBuf::from(filename)).to_string()
+                    });
+                (Vec::new(), Some(file.upload))
+            }
+        };
+        Ok(FormData {
+            name: self.name,
+            filename: self.filename,
+            content_type: self.content_type,
+            data,
+            file,
+        })
+    }
+}
+
+struct Buffered<R> {
+    reader: R,
+    buffer: Vec<u8>,
+}
+
+impl<R: Read + Unpin> Buffered<R> {
+    async fn fill(&mut self) -> io::Result<()> {
+        let mut chunk = [0; READ_CHUNK_SIZE];
+        let bytes_read = self.reader.read(&mut chunk).await?;
+        if bytes_read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "multipart body ended unexpectedly",
+            ));
+        }
+        self.buffer.extend_from_slice(&chunk[..bytes_read]);
+        Ok(())
+    }
+
+    async fn fill_to(&mut self, length: usize) -> io::Result<()> {
+        while self.buffer.len() < length {
+            self.fill().await?;
+        }
+        Ok(())
+    }
+
+    fn consume(&mut self, length: usize) {
+        self.buffer.drain(..length);
+    }
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len())
+        .position(|window| window == needle)
+}
+
+pub(crate) async fn parse<R: Read + Unpin>(
+    reader: R,
+    boundary: &str,
+    config: &RequestConfig,
+) -> io::Result<Vec<FormData>> {
+    let delimiter = format!("\r\n--{}", boundary).into_bytes();
+    // The first boundary isn't necessarily preceded by a line break,
+    // pretending it is lets us look for the same delimiter everywhere
+    let mut input = Buffered {
+        reader,
+        buffer: b"\r\n".to_vec(),
+    };
+
+    // Skip the preamble
+    loop {
+        if let Some(index) = find(&input.buffer, &delimiter) {
+            input.consume(index + delimiter.len());
+            break;
+        }
+        let keep = input.buffer.len().saturating_sub(delimiter.len() - 1);
+        input.consume(keep);
+        input.fill().await?;
+    }
+
+    let mut form_data = Vec::new();
+    let mut size = 0;
+    loop {
+        // A delimiter is either followed by "--" (end of body) or a line break and the next part
+        input.fill_to(2).await?;
+        if input.buffer.starts_with(b"--") {
+            break;
+        }
+        let line_end = loop {
+            if let Some(index) = find(&input.buffer, b"\r\n") {
+                break index;
+            }
+            if input.buffer.len() > MAX_PART_HEADER_SIZE {
+                return Err(invalid_data("malformed multipart boundary"));
+            }
+            input.fill().await?;
+        };
+        input.consume(line_end + 2);
+
+        // Part headers, which may be empty
+        let header_end = loop {
+            if input.buffer.starts_with(b"\r\n") {
+                break 0;
+            }
+            if let Some(index) = find(&input.buffer, b"\r\n\r\n") {
+                break index + 2;
+            }
+            if input.buffer.len() > MAX_PART_HEADER_SIZE {
+                return Err(invalid_data("multipart part headers are too large"));
+            }
+            input.fill().await?;
+        };
+        let headers = String::from_utf8_lossy(&input.buffer[..header_end]).to_string();
+        input.consume(header_end + 2);
+        let (name, filename, content_type) = parse_part_headers(&headers);
+
+        let mut part = Part {
+            name,
+            filename,
+            content_type,
+            head: Vec::new(),
+            sink: Sink::Memory(Vec::new()),
+            size: 0,
+        };
+        loop {
+            if let Some(index) = find(&input.buffer, &delimiter) {
+                part.write(&input.buffer[..index], size, config).await?;
+                input.consume(index + delimiter.len());
+                break;
+            }
+            // The end of the buffer might be the beginning of a delimiter
+            let safe = input.buffer.len().saturating_sub(delimiter.len() - 1);
+            if safe > 0 {
+                part.write(&input.buffer[..safe], size, config).await?;
+                input.consume(safe);
+            }
+            input.fill().await?;
+        }
+        size += part.size;
+        form_data.push(part.finish().await?);
+    }
+    Ok(form_data)
+}
+
+fn parse_part_headers(headers: &str) -> (String, Option<String>, Option<String>) {
+    let mut name = String::new();
+    let mut filename = None;
+    let mut content_type = None;
+    for line in headers.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        if key.trim().eq_ignore_ascii_case("Content-Disposition") {
+            for attr in value.split(';').skip(1) {
+                let attr = attr.trim();
+                if let Some(value) = attr.strip_prefix("name=") {
+                    name = value.trim_matches('"').to_string();
+                } else if let Some(value) = attr.strip_prefix("filename=") {
+                    filename = Some(value.trim_matches('"').to_string());
+                }
+            }
+        } else if key.trim().eq_ignore_ascii_case("Content-Type") {
+            content_type = Some(value.trim().to_string());
+        }
+    }
+    (name, filename, content_type)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    const BOUNDARY: &str = "----surfer-boundary";
+
+    // Hands out the body a few bytes per read, like a slow connection
+    struct Chunked<'a> {
+        data: &'a [u8],
+        chunk_size: usize,
+    }
+
+    impl Read for Chunked<'_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let length = self.chunk_size.min(buf.len()).min(self.data.len());
+            buf[..length].copy_from_slice(&self.data[..length]);
+            self.data = &self.data[length..];
+            Poll::Ready(Ok(length))
+        }
+    }
+
+    fn body(parts: &[(&str, Option<&str>, &[u8])]) -> Vec<u8> {
+        let mut body = b"preamble\r\n".to_vec();
+        for (name, filename, data) in parts {
+            body.extend_from_slice(format!("--{}\r\n", BOUNDARY).as_bytes());
+            let disposition = match filename {
+                Some(filename) => format!("name=\"{}\"; filename=\"{}\"", name, filename),
+                None => format!("name=\"{}\"", name),
+            };
+            body.extend_from_slice(
+                format!("Content-Disposition: form-data; {}\r\n\r\n", disposition).as_bytes(),
+            );
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+        body
+    }
+
+    fn config(spool_threshold: Option<usize>) -> RequestConfig {
+        RequestConfig {
+            spool_threshold,
+            ..RequestConfig::default()
+        }
+    }
+
+    async fn parse_body(body: &[u8], config: &RequestConfig) -> io::Result<Vec<FormData>> {
+        parse(
+            Chunked {
+                data: body,
+                chunk_size: READ_CHUNK_SIZE,
+            },
+            BOUNDARY,
+            config,
+        )
+        .await
+    }
+
+    #[async_std::test]
+    async fn finds_boundaries_split_across_reads() {
+        // Looks like the start of a delimiter, but isn't one
+        let tricky = format!("a\r\n--{}x\r\n-", &BOUNDARY[..10]);
+        let body = body(&[
+            ("title", None, b"Hello"),
+            ("text", None, tricky.as_bytes()),
+            ("empty", None, b""),
+        ]);
+        for chunk_size in 1..=body.len() {
+            let reader = Chunked {
+                data: &body,
+                chunk_size,
+            };
+            let form_data = parse(reader, BOUNDARY, &config(None)).await.unwrap();
+            let parts: Vec<(&str, &[u8])> = form_data
+                .iter()
+                .map(|part| (part.name.as_str(), part.data.as_slice()))
+                .collect();
+            assert_eq!(
+                parts,
+                [
+                    ("title", &b"Hello"[..]),
+                    ("text", tricky.as_bytes()),
+                    ("empty", &b""[..]),
+                ],
+                "chunk size {}",
+                chunk_size
+            );
+        }
+    }
+
+    #[async_std::test]
+    async fn spools_file_parts_above_the_threshold() {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.resize(100, 0);
+        let large_text = [b'x'; 100];
+        let body = body(&[
+            ("small", Some("small.txt"), b"tiny"),
+            ("image", Some("upload.bin"), &png),
+            ("comment", None, &large_text),
+        ]);
+        let form_data = parse_body(&body, &config(Some(10))).await.unwrap();
+
+        assert_eq!(form_data[0].data, b"tiny");
+        assert!(form_data[0].file.is_none());
+
+        let file = form_data[1].file.as_ref().unwrap();
+        assert!(form_data[1].data.is_empty());
+        assert_eq!(file.size(), 100);
+        assert_eq!(file.content_type(), "image/png");
+        assert_eq!(fs::read(file.path()).await.unwrap(), png);
+
+        // Only file parts are spooled
+        assert_eq!(form_data[2].data, large_text);
+        assert!(form_data[2].file.is_none());
+    }
+
+    #[async_std::test]
+    async fn removes_temp_files_unless_persisted() {
+        let body = body(&[
+            ("first", Some("a.txt"), &[b'a'; 64]),
+            ("second", Some("b.txt"), &[b'b'; 64]),
+        ]);
+        let mut form_data = parse_body(&body, &config(Some(10))).await.unwrap();
+        let temp_path = form_data[0].file.as_ref().unwrap().path().to_path_buf();
+        assert!(temp_path.exists().await);
+
+        let target = std::env::temp_dir().join(format!(
+            "surfer-persisted-{}-{}",
+            std::process::id(),
+            TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let second = form_data[1].file.as_mut().unwrap();
+        let second_temp_path = second.path().to_path_buf();
+        second.persist(&target).await.unwrap();
+        assert_eq!(second.path(), Path::new(&target));
+
+        drop(form_data);
+        assert!(!temp_path.exists().await);
+        assert!(!second_temp_path.exists().await);
+        assert_eq!(fs::read(&target).await.unwrap(), [b'b'; 64]);
+        fs::remove_file(&target).await.unwrap();
+    }
+
+    #[async_std::test]
+    async fn rejects_a_missing_closing_boundary() {
+        let mut body = body(&[("title", None, b"Hello")]);
+        body.truncate(body.len() - BOUNDARY.len() - 6);
+        let error = parse_body(&body, &config(None)).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[async_std::test]
+    async fn enforces_part_and_total_limits() {
+        let body = body(&[
+            ("first", Some("a.txt"), &[b'a'; 60]),
+            ("second", None, &[b'b'; 60]),
+        ]);
+        let limited = |max_part_size, max_multipart_size| RequestConfig {
+            max_part_size,
+            max_multipart_size,
+            ..config(Some(10))
+        };
+
+        assert!(parse_body(&body, &limited(Some(60), Some(120)))
+            .await
+            .is_ok());
+
+        let error = parse_body(&body, &limited(Some(59), None))
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "multipart part is too large");
+
+        let error = parse_body(&body, &limited(None, Some(119)))
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "multipart body is too large");
+    }
+}
@@ -1,32 +1,117 @@
+pub mod body;
+pub mod bytes_response;
 pub mod file_response;
 pub mod html_response;
 pub mod json_response;
+pub mod negotiate;
+pub mod redirect;
+pub mod serialized_response;
+pub mod sse_response;
+pub mod status_code;
+pub mod template_response;
+pub mod text_response;
 
 use async_std::{net::TcpStream, prelude::*};
+use futures::future::BoxFuture;
+use serde::Serialize;
 use std::collections::HashMap;
+use std::fmt;
+use std::net::Shutdown;
+
+use crate::cookie::{Cookie, CookieError, CookieJar};
+use crate::logs::Logger;
+pub use body::Body;
+use crate::utils::format_http_date;
+use chrono::Utc;
+pub use status_code::StatusCode;
 
 #[derive(Debug)]
 pub struct Response {
-    pub status_code: u16,
+    pub status_code: StatusCode,
     pub headers: HashMap<String, String>,
-    pub body: Option<Vec<u8>>,
+    // Sent as one `Set-Cookie` header each
+    pub cookies: Vec<Cookie>,
+    pub body: Body,
+    // Takes over the connection after a `101 Switching Protocols` response has been sent
+    pub upgrade: Option<OnUpgrade>,
+}
+
+pub struct OnUpgrade(Box<dyn FnOnce(TcpStream) -> BoxFuture<'static, ()> + Send>);
+
+impl OnUpgrade {
+    pub(crate) async fn run(self, stream: TcpStream) {
+        (self.0)(stream).await
+    }
+}
+
+impl fmt::Debug for OnUpgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OnUpgrade")
+    }
 }
 
 pub trait IntoResponse {
     fn into_response(self) -> impl std::future::Future<Output = Response> + Send;
 }
 
+impl IntoResponse for Response {
+    async fn into_response(self) -> Response {
+        self
+    }
+}
+
 impl Response {
-    pub fn new(status_code: u16) -> Response {
+    pub fn new(status_code: impl Into<StatusCode>) -> Response {
         Response {
-            status_code,
+            status_code: status_code.into(),
             headers: HashMap::new(),
-            body: None,
+            cookies: Vec::new(),
+            body: Body::Empty,
+            upgrade: None,
+        }
+    }
+
+    pub fn builder() -> ResponseBuilder {
+        ResponseBuilder {
+            response: Response::new(StatusCode::OK),
+        }
+    }
+
+    pub fn text(body: impl Into<String>) -> Response {
+        Response::builder()
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(body.into())
+    }
+
+    pub fn html(body: impl Into<String>) -> Response {
+        Response::builder()
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(body.into())
+    }
+
+    pub fn json<T: Serialize + ?Sized>(value: &T) -> Response {
+        match serde_json::to_vec(value) {
+            Ok(body) => Response::builder()
+                .header("Content-Type", "application/json")
+                .body(body),
+            Err(_) => Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .header("Content-Type", "text/plain; charset=utf-8")
+                .body("500 Internal Server Error"),
         }
     }
 
-    pub fn with_body(mut self, body: Vec<u8>) -> Response {
-        self.body = Some(body);
+    // 302 Found, see `redirect::Redirect` for the other kinds of redirects
+    pub fn redirect(location: &str) -> Response {
+        redirect::Redirect::to(location).into()
+    }
+
+    pub fn no_content() -> Response {
+        Response::new(StatusCode::NO_CONTENT)
+    }
+
+    pub fn with_body(mut self, body: impl Into<Body>) -> Response {
+        self.body = body.into();
         self
     }
 
@@ -35,30 +120,197 @@ impl Response {
         self
     }
 
+    // Invalid cookies are logged and left out, use `add_cookie` to handle them yourself
+    pub fn with_cookie(mut self, cookie: Cookie) -> Response {
+        self.add_cookie_or_log(cookie);
+        self
+    }
+
+    // Sends a `Set-Cookie` header for every cookie added to or removed from the jar
+    pub fn with_cookie_jar(mut self, jar: &CookieJar) -> Response {
+        for cookie in jar.delta() {
+            self.add_cookie_or_log(cookie.clone());
+        }
+        self
+    }
+
+    // Replaces a cookie with the same name, path and domain, browsers would only keep the last one.
+    // Cookies that can't be sent as they are, see `Cookie::validate`, are rejected.
+    pub fn add_cookie(&mut self, cookie: Cookie) -> Result<(), CookieError> {
+        cookie.validate()?;
+        self.cookies.retain(|existing| !existing.is_same(&cookie));
+        self.cookies.push(cookie);
+        Ok(())
+    }
+
+    fn add_cookie_or_log(&mut self, cookie: Cookie) {
+        if let Err(err) = self.add_cookie(cookie) {
+            Logger::new().error(&format!("Not setting invalid cookie: {}", err));
+        }
+    }
+
+    pub fn with_upgrade<F, Fut>(mut self, on_upgrade: F) -> Response
+    where
+        F: FnOnce(TcpStream) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.upgrade = Some(OnUpgrade(Box::new(move |stream| {
+            Box::pin(on_upgrade(stream))
+        })));
+        self
+    }
+
+    // Header names are case-insensitive, so look them up accordingly
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Replaces any header with the same name, regardless of its case
+    pub fn set_header(&mut self, name: &str, value: impl ToString) {
+        self.remove_header(name);
+        self.headers.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn remove_header(&mut self, name: &str) -> Option<String> {
+        let key = self
+            .headers
+            .keys()
+            .find(|key| key.eq_ignore_ascii_case(name))?
+            .clone();
+        self.headers.remove(&key)
+    }
+
+    // Adds `name` to the Vary header unless it's listed already
+    pub fn add_vary(&mut self, name: &str) {
+        let vary = match self.header("Vary") {
+            Some(vary)
+                if vary
+                    .split(',')
+                    .any(|field| field.trim() == "*" || field.trim().eq_ignore_ascii_case(name)) =>
+            {
+                return;
+            }
+            Some(vary) if !vary.trim().is_empty() => format!("{}, {}", vary, name),
+            _ => name.to_string(),
+        };
+        self.set_header("Vary", vary);
+    }
+
+    // Whether the status code allows a body at all (RFC 9110, 6.4.1)
+    pub fn allows_body(&self) -> bool {
+        !(self.status_code.is_informational()
+            || self.status_code == StatusCode::NO_CONTENT
+            || self.status_code == StatusCode::NOT_MODIFIED)
+    }
+
     pub async fn send(&mut self, stream: &mut TcpStream) {
+        self.write(stream, true).await;
+    }
+
+    // Sends status line and headers only, as the answer to a HEAD request
+    pub async fn send_head(&mut self, stream: &mut TcpStream) {
+        self.write(stream, false).await;
+    }
+
+    async fn write(&mut self, stream: &mut TcpStream, include_body: bool) {
+        self.prepare_headers();
+
         let mut response = format!("HTTP/1.1 {}\r\n", self.status_code);
         for (key, value) in &self.headers {
             response.push_str(&format!("{}: {}\r\n", key, value));
         }
+        for cookie in &self.cookies {
+            // `cookies` is public, so cookies may have bypassed `add_cookie`
+            if let Err(err) = cookie.validate() {
+                Logger::new().error(&format!("Skipping invalid cookie: {}", err));
+                continue;
+            }
+            response.push_str(&format!("Set-Cookie: {}\r\n", cookie));
+        }
         response.push_str("\r\n");
 
-        let mut response = response.as_bytes().to_vec();
-        if self.body.is_some() {
-            response = [
-                response,
-                <Option<Vec<u8>> as Clone>::clone(&self.body).unwrap(),
-            ]
-            .concat();
+        if let Err(err) = stream.write_all(response.as_bytes()).await {
+            Logger::new().error(&format!("Error writing response: {}", err));
+            return;
+        }
+        if include_body {
+            if let Err(err) = std::mem::take(&mut self.body).write_to(stream).await {
+                // The response is incomplete, closing the connection is the only way left
+                // to tell the client
+                Logger::new().error(&format!("Error writing response body: {}", err));
+                let _ = stream.shutdown(Shutdown::Both);
+                return;
+            }
         }
 
-        if stream.write_all(&response).await.is_err() {
-            println!("Error writing response");
-            return;
+        if let Err(err) = stream.flush().await {
+            Logger::new().error(&format!("Error flushing response: {}", err));
         }
+    }
 
-        if stream.flush().await.is_err() {
-            println!("Error flushing response");
-            return;
+    // Framing is derived from the body, whatever handlers set themselves
+    fn prepare_headers(&mut self) {
+        self.remove_header("Content-Length");
+        self.remove_header("Transfer-Encoding");
+        if !self.allows_body() {
+            self.body = Body::Empty;
+        } else if let Some(length) = self.body.len() {
+            self.headers
+                .insert("Content-Length".to_string(), length.to_string());
+        } else {
+            self.headers
+                .insert("Transfer-Encoding".to_string(), "chunked".to_string());
+        }
+        if self.header("Date").is_none() {
+            self.headers
+                .insert("Date".to_string(), format_http_date(Utc::now()));
         }
+        // Connections aren't reused, tell the client so
+        if self.header("Connection").is_none() {
+            self.headers
+                .insert("Connection".to_string(), "close".to_string());
+        }
+    }
+}
+
+pub struct ResponseBuilder {
+    response: Response,
+}
+
+impl ResponseBuilder {
+    pub fn status(mut self, status_code: impl Into<StatusCode>) -> ResponseBuilder {
+        self.response.status_code = status_code.into();
+        self
+    }
+
+    pub fn header(mut self, key: &str, value: impl ToString) -> ResponseBuilder {
+        self.response
+            .headers
+            .insert(key.to_string(), value.to_string());
+        self
+    }
+
+    // Invalid cookies are logged and left out, like with `Response::with_cookie`
+    pub fn cookie(mut self, cookie: Cookie) -> ResponseBuilder {
+        self.response.add_cookie_or_log(cookie);
+        self
+    }
+
+    pub fn cookie_jar(mut self, jar: &CookieJar) -> ResponseBuilder {
+        self.response = self.response.with_cookie_jar(jar);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Body>) -> Response {
+        self.response.body = body.into();
+        self.response
+    }
+
+    // Finishes the response without a body
+    pub fn build(self) -> Response {
+        self.response
     }
 }
@@ -0,0 +1,207 @@
+use async_std::io::{self, Read, ReadExt};
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use futures::stream::{self, BoxStream};
+use std::fmt;
+use std::pin::Pin;
+
+const CHUNK_SIZE: usize = 16 * 1024;
+
+pub type BodyStream = BoxStream<'static, io::Result<Vec<u8>>>;
+pub type BodyReader = Pin<Box<dyn Read + Send>>;
+
+// Response body. Bodies with an unknown length are sent with `Transfer-Encoding: chunked`.
+#[derive(Default)]
+pub enum Body {
+    #[default]
+    Empty,
+    Bytes(Vec<u8>),
+    Stream {
+        stream: BodyStream,
+        length: Option<u64>,
+    },
+    Reader {
+        reader: BodyReader,
+        length: Option<u64>,
+    },
+}
+
+impl Body {
+    pub fn from_stream<S>(stream: S) -> Body
+    where
+        S: Stream<Item = io::Result<Vec<u8>>> + Send + 'static,
+    {
+        Body::Stream {
+            stream: Box::pin(stream),
+            length: None,
+        }
+    }
+
+    // `length` must match the number of bytes the reader yields, if given
+    pub fn from_reader<R>(reader: R, length: Option<u64>) -> Body
+    where
+        R: Read + Send + 'static,
+    {
+        Body::Reader {
+            reader: Box::pin(reader),
+            length,
+        }
+    }
+
+    // Length of the body, `None` if it isn't known upfront
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Empty => Some(0),
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::Stream { length, .. } | Body::Reader { length, .. } => *length,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    // Only available for bodies that are already in memory
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Empty => Some(&[]),
+            Body::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn into_stream(self) -> BodyStream {
+        match self {
+            Body::Empty => Box::pin(stream::empty()),
+            Body::Bytes(bytes) => Box::pin(stream::once(async move { Ok(bytes) })),
+            Body::Stream { stream, .. } => stream,
+            Body::Reader { reader, .. } => Box::pin(stream::unfold(reader, |mut reader| async {
+                let mut buffer = vec![0; CHUNK_SIZE];
+                match reader.read(&mut buffer).await {
+                    Ok(0) => None,
+                    Ok(bytes_read) => {
+                        buffer.truncate(bytes_read);
+                        Some((Ok(buffer), reader))
+                    }
+                    Err(err) => Some((Err(err), reader)),
+                }
+            })),
+        }
+    }
+
+    // Reads the whole body into memory
+    pub async fn into_bytes(self) -> io::Result<Vec<u8>> {
+        match self {
+            Body::Empty => Ok(Vec::new()),
+            Body::Bytes(bytes) => Ok(bytes),
+            body => {
+                let mut bytes = Vec::new();
+                let mut stream = body.into_stream();
+                while let Some(chunk) = stream.next().await {
+                    bytes.extend_from_slice(&chunk?);
+                }
+                Ok(bytes)
+            }
+        }
+    }
+
+    pub(crate) async fn write_to(self, stream: &mut TcpStream) -> io::Result<()> {
+        match self {
+            Body::Empty => Ok(()),
+            Body::Bytes(bytes) => stream.write_all(&bytes).await,
+            Body::Reader {
+                reader,
+                length: Some(length),
+            } => {
+                let written = io::copy(&mut reader.take(length), stream).await?;
+                check_length(written, length)
+            }
+            Body::Stream {
+                stream: mut chunks,
+                length: Some(length),
+            } => {
+                let mut written = 0;
+                while let Some(chunk) = chunks.next().await {
+                    let chunk = chunk?;
+                    written += chunk.len() as u64;
+                    if written > length {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("body is longer than its length of {} bytes", length),
+                        ));
+                    }
+                    stream.write_all(&chunk).await?;
+                }
+                check_length(written, length)
+            }
+            body => {
+                let mut chunks = body.into_stream();
+                while let Some(chunk) = chunks.next().await {
+                    let chunk = chunk?;
+                    // An empty chunk would end the body
+                    if chunk.is_empty() {
+                        continue;
+                    }
+                    stream
+                        .write_all(format!("{:x}\r\n", chunk.len()).as_bytes())
+                        .await?;
+                    stream.write_all(&chunk).await?;
+                    stream.write_all(b"\r\n").await?;
+                    stream.flush().await?;
+                }
+                stream.write_all(b"0\r\n\r\n").await
+            }
+        }
+    }
+}
+
+// The client expects exactly as many bytes as `Content-Length` says, a body that ends early
+// leaves it waiting for the rest
+fn check_length(written: u64, length: u64) -> io::Result<()> {
+    if written < length {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("body ended after {} of {} bytes", written, length),
+        ));
+    }
+    Ok(())
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Body::Empty => f.write_str("Empty"),
+            Body::Bytes(bytes) => f.debug_tuple("Bytes").field(bytes).finish(),
+            Body::Stream { length, .. } => {
+                f.debug_struct("Stream").field("length", length).finish()
+            }
+            Body::Reader { length, .. } => {
+                f.debug_struct("Reader").field("length", length).finish()
+            }
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body::Bytes(bytes)
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Body::Bytes(text.into_bytes())
+    }
+}
+
+impl From<&str> for Body {
+    fn from(text: &str) -> Self {
+        Body::Bytes(text.as_bytes().to_vec())
+    }
+}
+
+impl From<&[u8]> for Body {
+    fn from(bytes: &[u8]) -> Self {
+        Body::Bytes(bytes.to_vec())
+    }
+}
@@ -0,0 +1,19 @@
+use crate::response::{IntoResponse, Response};
+use std::collections::HashMap;
+
+pub struct BytesResponse {
+    pub status_code: u16,
+    pub content: Vec<u8>,
+    pub content_type: String,
+    pub headers: Option<HashMap<String, String>>,
+}
+
+impl IntoResponse for BytesResponse {
+    async fn into_response(self) -> Response {
+        let mut headers: HashMap<String, String> = self.headers.unwrap_or_default();
+        headers.insert("Content-Type".to_string(), self.content_type);
+        Response::new(self.status_code)
+            .with_body(self.content)
+            .with_headers(headers)
+    }
+}
@@ -1,8 +1,8 @@
 use crate::{
-    response::{IntoResponse, Response},
+    response::{Body, IntoResponse, Response},
     utils::get_content_type,
 };
-use async_std::{fs, path::PathBuf};
+use async_std::{fs::File, path::PathBuf};
 use std::collections::HashMap;
 
 pub struct FileResponse {
@@ -15,15 +15,21 @@ impl IntoResponse for FileResponse {
     async fn into_response(self) -> Response {
         let file_path = PathBuf::from(self.file_path);
         let content_type = get_content_type(&file_path);
+        // The file is streamed instead of being read into memory upfront
         let content = if file_path.exists().await && file_path.is_file().await {
-            fs::read(&file_path).await.unwrap_or_else(|_| Vec::new())
+            match File::open(&file_path).await {
+                Ok(file) => {
+                    let length = file.metadata().await.map(|metadata| metadata.len()).ok();
+                    Body::from_reader(file, length)
+                }
+                Err(_) => Body::Empty,
+            }
         } else {
-            b"404 Not Found".to_vec()
+            Body::from("404 Not Found")
         };
 
-        let mut headers: HashMap<String, String> = self.headers.unwrap_or(HashMap::new());
+        let mut headers: HashMap<String, String> = self.headers.unwrap_or_default();
         headers.insert("Content-Type".to_string(), content_type.to_string());
-        headers.insert("Content-Length".to_string(), content.len().to_string());
         Response::new(self.status_code)
             .with_body(content)
             .with_headers(headers)
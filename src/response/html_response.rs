@@ -9,8 +9,8 @@ pub struct HtmlResponse {
 
 impl IntoResponse for HtmlResponse {
     async fn into_response(self) -> Response {
-        let mut headers: HashMap<String, String> = self.headers.unwrap_or(HashMap::new());
-        headers.insert("Content-Type".to_string(), "text/html".to_string());
+        let mut headers: HashMap<String, String> = self.headers.unwrap_or_default();
+        headers.insert("Content-Type".to_string(), "text/html; charset=utf-8".to_string());
         Response::new(self.status_code)
             .with_body(self.content.into_bytes())
             .with_headers(headers)
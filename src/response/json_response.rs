@@ -16,9 +16,8 @@ impl<T: serde::Serialize + Send> IntoResponse for JsonResponse<T> {
         T: Send,
     {
         let body = serde_json::to_vec(&self.body).unwrap();
-        let mut headers = self.headers.unwrap_or(HashMap::new());
+        let mut headers = self.headers.unwrap_or_default();
         headers.insert("Content-Type".to_string(), "application/json".to_string());
-        headers.insert("Content-Length".to_string(), body.len().to_string());
         Response::new(self.status_code)
             .with_body(body)
             .with_headers(headers)
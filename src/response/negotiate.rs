@@ -0,0 +1,188 @@
+use crate::request::accept::{best_match, Accept, AcceptLanguage};
+use crate::request::Request;
+use crate::response::{IntoResponse, Response, StatusCode};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+
+type Render = Box<dyn FnOnce() -> BoxFuture<'static, Response> + Send>;
+
+struct Representation {
+    media_type: String,
+    language: Option<String>,
+    render: Render,
+}
+
+// Answers with whichever registered representation the client prefers according to
+// its Accept and Accept-Language headers, or 406 Not Acceptable if it takes none of them.
+// Only the chosen representation is rendered.
+//
+//     Negotiate::new(&request)
+//         .with("application/json", move || JsonResponse {
+//             status_code: 200,
+//             headers: None,
+//             body: user,
+//         })
+//         .with("text/html", move || HtmlResponse { .. })
+pub struct Negotiate {
+    accept: Accept,
+    accept_language: AcceptLanguage,
+    representations: Vec<Representation>,
+}
+
+impl Negotiate {
+    pub fn new(request: &Request) -> Negotiate {
+        Negotiate {
+            accept: Accept::parse(request.header("Accept")),
+            accept_language: AcceptLanguage::parse(request.header("Accept-Language")),
+            representations: Vec::new(),
+        }
+    }
+
+    // Representations registered first are preferred if the client accepts several equally
+    pub fn with<F, R>(self, media_type: &str, render: F) -> Negotiate
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: IntoResponse + 'static,
+    {
+        self.add(media_type, None, render)
+    }
+
+    pub fn with_language<F, R>(self, media_type: &str, language: &str, render: F) -> Negotiate
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: IntoResponse + 'static,
+    {
+        self.add(media_type, Some(language), render)
+    }
+
+    fn add<F, R>(mut self, media_type: &str, language: Option<&str>, render: F) -> Negotiate
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: IntoResponse + 'static,
+    {
+        self.representations.push(Representation {
+            media_type: media_type.to_string(),
+            language: language.map(str::to_string),
+            render: Box::new(move || render().into_response().boxed()),
+        });
+        self
+    }
+
+    fn quality(&self, representation: &Representation) -> f32 {
+        let language_quality = match &representation.language {
+            Some(language) => self.accept_language.quality(language),
+            None => 1.0,
+        };
+        self.accept.quality(&representation.media_type) * language_quality
+    }
+
+    // The headers the choice depends on, which caches have to take into account
+    fn vary(&self) -> Vec<&'static str> {
+        let mut vary = vec!["Accept"];
+        if self.representations.iter().any(|r| r.language.is_some()) {
+            vary.push("Accept-Language");
+        }
+        vary
+    }
+}
+
+impl IntoResponse for Negotiate {
+    async fn into_response(mut self) -> Response {
+        let vary = self.vary();
+        let chosen = best_match(0..self.representations.len(), |index| {
+            self.quality(&self.representations[*index])
+        });
+
+        let Some(index) = chosen else {
+            let mut available: Vec<&str> = Vec::new();
+            for representation in &self.representations {
+                if !available.contains(&representation.media_type.as_str()) {
+                    available.push(&representation.media_type);
+                }
+            }
+            let mut response = Response::builder()
+                .status(StatusCode::NOT_ACCEPTABLE)
+                .header("Content-Type", "text/plain; charset=utf-8")
+                .body(format!(
+                    "406 Not Acceptable: available types are {}",
+                    available.join(", ")
+                ));
+            for name in vary {
+                response.add_vary(name);
+            }
+            return response;
+        };
+
+        let representation = self.representations.swap_remove(index);
+        let mut response = (representation.render)().await;
+        if response.header("Content-Type").is_none() {
+            response.set_header("Content-Type", &representation.media_type);
+        }
+        if let Some(language) = &representation.language {
+            if response.header("Content-Language").is_none() {
+                response.set_header("Content-Language", language);
+            }
+        }
+        for name in vary {
+            response.add_vary(name);
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::html_response::HtmlResponse;
+    use crate::response::json_response::JsonResponse;
+    use serde_json::json;
+
+    fn request(accept: &str) -> Request {
+        let mut request = Request::new();
+        request
+            .headers
+            .insert("Accept".to_string(), accept.to_string());
+        request
+    }
+
+    fn negotiate(request: &Request) -> Negotiate {
+        let user = json!({ "name": "Jane" });
+        Negotiate::new(request)
+            .with("application/json", move || JsonResponse {
+                status_code: 200,
+                headers: None,
+                body: user,
+            })
+            .with("text/html", move || HtmlResponse {
+                status_code: 200,
+                content: "<p>Jane</p>".to_string(),
+                headers: None,
+            })
+    }
+
+    #[async_std::test]
+    async fn renders_the_preferred_representation() {
+        let response = negotiate(&request("text/html, application/json;q=0.9"))
+            .into_response()
+            .await;
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(response.header("Vary"), Some("Accept"));
+
+        // Registration order breaks ties
+        let response = negotiate(&request("*/*")).into_response().await;
+        assert_eq!(response.header("Content-Type"), Some("application/json"));
+    }
+
+    #[async_std::test]
+    async fn answers_406_if_nothing_is_acceptable() {
+        let response = negotiate(&request("image/png")).into_response().await;
+        assert_eq!(response.status_code, StatusCode::NOT_ACCEPTABLE);
+        assert_eq!(
+            response.body.as_bytes(),
+            Some(&b"406 Not Acceptable: available types are application/json, text/html"[..])
+        );
+    }
+}
@@ -0,0 +1,76 @@
+use crate::response::{IntoResponse, Response, StatusCode};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+
+// Characters that may not appear in a URI reference. `%` is left alone so that
+// already encoded locations stay as they are.
+const LOCATION: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'<')
+    .add(b'>')
+    .add(b'\\')
+    .add(b'^')
+    .add(b'`')
+    .add(b'{')
+    .add(b'|')
+    .add(b'}');
+
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    status_code: StatusCode,
+    location: String,
+}
+
+impl Redirect {
+    // 302 Found, the generic redirect. Clients may change POST into GET.
+    pub fn to(location: &str) -> Redirect {
+        Redirect::with_status(StatusCode::FOUND, location)
+    }
+
+    // 303 See Other, e.g. after a form submission. The target is always fetched with GET.
+    pub fn see_other(location: &str) -> Redirect {
+        Redirect::with_status(StatusCode::SEE_OTHER, location)
+    }
+
+    // 307 Temporary Redirect, the method and body of the request are kept
+    pub fn temporary(location: &str) -> Redirect {
+        Redirect::with_status(StatusCode::TEMPORARY_REDIRECT, location)
+    }
+
+    // 308 Permanent Redirect, the method and body of the request are kept
+    // and clients may cache the redirect
+    pub fn permanent(location: &str) -> Redirect {
+        Redirect::with_status(StatusCode::PERMANENT_REDIRECT, location)
+    }
+
+    fn with_status(status_code: StatusCode, location: &str) -> Redirect {
+        Redirect {
+            status_code,
+            location: utf8_percent_encode(location, LOCATION).to_string(),
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        self.status_code
+    }
+
+    // The encoded value of the Location header
+    pub fn location(&self) -> &str {
+        &self.location
+    }
+}
+
+impl From<Redirect> for Response {
+    fn from(redirect: Redirect) -> Response {
+        Response::builder()
+            .status(redirect.status_code)
+            .header("Location", redirect.location)
+            .build()
+    }
+}
+
+impl IntoResponse for Redirect {
+    async fn into_response(self) -> Response {
+        self.into()
+    }
+}
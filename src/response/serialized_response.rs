@@ -0,0 +1,119 @@
+use crate::request::accept::{best_match, Accept};
+use crate::request::Request;
+use crate::response::{IntoResponse, Response, StatusCode};
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Format {
+    // In order of preference if the client accepts several equally
+    const ALL: [Format; 3] = [Format::Json, Format::MessagePack, Format::Cbor];
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::MessagePack => "application/msgpack",
+            Format::Cbor => "application/cbor",
+        }
+    }
+
+    fn media_types(&self) -> &'static [&'static str] {
+        match self {
+            Format::Json => &["application/json"],
+            Format::MessagePack => &[
+                "application/msgpack",
+                "application/x-msgpack",
+                "application/vnd.msgpack",
+            ],
+            Format::Cbor => &["application/cbor"],
+        }
+    }
+
+    // Picks the format the client prefers, `None` if it accepts none of them.
+    // Without an Accept header JSON is used.
+    pub fn from_accept(accept: Option<&str>) -> Option<Format> {
+        let accept = Accept::parse(accept);
+        best_match(Format::ALL, |format| {
+            format
+                .media_types()
+                .iter()
+                .map(|media_type| accept.quality(media_type))
+                .fold(0.0, f32::max)
+        })
+    }
+
+    pub fn serialize<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Format::Json => serde_json::to_vec(value).map_err(|err| err.to_string()),
+            Format::MessagePack => rmp_serde::to_vec_named(value).map_err(|err| err.to_string()),
+            Format::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(value, &mut bytes).map_err(|err| err.to_string())?;
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+// Serializes `body` as JSON, MessagePack or CBOR, whichever the client prefers
+// according to its Accept header. Answers with 406 Not Acceptable if it takes none of them.
+pub struct SerializedResponse<T> {
+    pub status_code: u16,
+    pub body: T,
+    // Accept header of the request
+    pub accept: Option<String>,
+    pub headers: Option<HashMap<String, String>>,
+}
+
+impl<T> SerializedResponse<T> {
+    pub fn for_request(request: &Request, status_code: u16, body: T) -> SerializedResponse<T> {
+        SerializedResponse {
+            status_code,
+            body,
+            accept: request.header("Accept").map(str::to_string),
+            headers: None,
+        }
+    }
+}
+
+impl<T: Serialize + Send> IntoResponse for SerializedResponse<T> {
+    async fn into_response(self) -> Response {
+        let Some(format) = Format::from_accept(self.accept.as_deref()) else {
+            let supported: Vec<&str> = Format::ALL.iter().map(Format::content_type).collect();
+            return Response::builder()
+                .status(StatusCode::NOT_ACCEPTABLE)
+                .header("Content-Type", "text/plain; charset=utf-8")
+                .header("Vary", "Accept")
+                .body(format!(
+                    "406 Not Acceptable: supported types are {}",
+                    supported.join(", ")
+                ));
+        };
+        let body = match format.serialize(&self.body) {
+            Ok(body) => body,
+            Err(_) => {
+                return Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .header("Content-Type", "text/plain; charset=utf-8")
+                    .body("500 Internal Server Error")
+            }
+        };
+
+        let mut headers = self.headers.unwrap_or_default();
+        headers.insert(
+            "Content-Type".to_string(),
+            format.content_type().to_string(),
+        );
+        let mut response = Response::new(self.status_code)
+            .with_body(body)
+            .with_headers(headers);
+        response.add_vary("Accept");
+        response
+    }
+}
@@ -0,0 +1,185 @@
+use crate::request::{FromRequest, Request};
+use crate::response::{Body, IntoResponse, Response};
+use async_std::future::timeout;
+use futures::channel::mpsc;
+use futures::stream::{self, BoxStream};
+use futures::{SinkExt, Stream, StreamExt};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+// Single server-sent event, see https://html.spec.whatwg.org/multipage/server-sent-events.html
+#[derive(Debug, Clone, Default)]
+pub struct Event {
+    pub id: Option<String>,
+    pub event: Option<String>,
+    pub data: Option<String>,
+    pub retry: Option<Duration>,
+    pub comment: Option<String>,
+}
+
+impl Event {
+    pub fn new(data: impl Into<String>) -> Event {
+        Event {
+            data: Some(data.into()),
+            ..Event::default()
+        }
+    }
+
+    pub fn json<T: serde::Serialize>(value: &T) -> serde_json::Result<Event> {
+        serde_json::to_string(value).map(Event::new)
+    }
+
+    pub fn with_id(mut self, id: impl Into<String>) -> Event {
+        self.id = Some(id.into());
+        self
+    }
+
+    pub fn with_event(mut self, event: impl Into<String>) -> Event {
+        self.event = Some(event.into());
+        self
+    }
+
+    pub fn with_retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+
+    pub fn with_comment(mut self, comment: impl Into<String>) -> Event {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut frame = String::new();
+        // Line breaks would start a new field, so every line gets its own field
+        let mut push_field = |name: &str, value: &str| {
+            for line in value.replace("\r\n", "\n").split(['\n', '\r']) {
+                frame.push_str(name);
+                frame.push(':');
+                if !line.is_empty() {
+                    frame.push(' ');
+                    frame.push_str(line);
+                }
+                frame.push('\n');
+            }
+        };
+        if let Some(comment) = &self.comment {
+            push_field("", comment);
+        }
+        if let Some(event) = &self.event {
+            push_field("event", event);
+        }
+        if let Some(id) = &self.id {
+            push_field("id", id);
+        }
+        if let Some(retry) = &self.retry {
+            push_field("retry", &retry.as_millis().to_string());
+        }
+        if let Some(data) = &self.data {
+            push_field("data", data);
+        }
+        frame.push('\n');
+        frame.into_bytes()
+    }
+}
+
+// Keeps the connection open and sends every event of `stream` as it arrives.
+// A comment is sent after `keep_alive` without events so proxies don't close the connection,
+// which also makes sure a disconnected client is noticed.
+pub struct SseResponse {
+    pub stream: BoxStream<'static, Event>,
+    pub keep_alive: Option<Duration>,
+    pub headers: Option<HashMap<String, String>>,
+}
+
+// Sending half of `SseResponse::channel`
+#[derive(Clone)]
+pub struct SseSender {
+    sender: mpsc::Sender<Event>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Disconnected;
+
+impl fmt::Display for Disconnected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("client disconnected")
+    }
+}
+
+impl std::error::Error for Disconnected {}
+
+impl SseResponse {
+    pub fn new<S>(stream: S) -> SseResponse
+    where
+        S: Stream<Item = Event> + Send + 'static,
+    {
+        SseResponse {
+            stream: stream.boxed(),
+            keep_alive: Some(DEFAULT_KEEP_ALIVE),
+            headers: None,
+        }
+    }
+
+    // Events sent through the returned sender are forwarded to the client.
+    // Sending fails once the client has disconnected.
+    pub fn channel() -> (SseSender, SseResponse) {
+        let (sender, receiver) = mpsc::channel(16);
+        (SseSender { sender }, SseResponse::new(receiver))
+    }
+
+    pub fn with_keep_alive(mut self, keep_alive: Option<Duration>) -> SseResponse {
+        self.keep_alive = keep_alive;
+        self
+    }
+}
+
+impl SseSender {
+    pub async fn send(&mut self, event: Event) -> Result<(), Disconnected> {
+        self.sender.send(event).await.map_err(|_| Disconnected)
+    }
+
+    pub fn is_disconnected(&self) -> bool {
+        self.sender.is_closed()
+    }
+}
+
+impl IntoResponse for SseResponse {
+    async fn into_response(self) -> Response {
+        let keep_alive = self.keep_alive;
+        let frames = stream::unfold(self.stream, move |mut events| async move {
+            let event = match keep_alive {
+                Some(keep_alive) => match timeout(keep_alive, events.next()).await {
+                    Ok(event) => event?,
+                    Err(_) => Event::default().with_comment(""),
+                },
+                None => events.next().await?,
+            };
+            Some((Ok(event.to_bytes()), events))
+        });
+
+        let mut headers = self.headers.unwrap_or_default();
+        headers.insert("Content-Type".to_string(), "text/event-stream".to_string());
+        headers.insert("Cache-Control".to_string(), "no-cache".to_string());
+        // Keeps reverse proxies like nginx from buffering the events
+        headers.insert("X-Accel-Buffering".to_string(), "no".to_string());
+        Response::new(200)
+            .with_body(Body::from_stream(frames))
+            .with_headers(headers)
+    }
+}
+
+// ID of the last event the client received before reconnecting
+#[derive(Debug, Clone, Default)]
+pub struct LastEventId(pub Option<String>);
+
+impl FromRequest for LastEventId {
+    fn from_request(request: &Request) -> Result<Self, Response> {
+        Ok(LastEventId(
+            request.header("Last-Event-ID").map(str::to_string),
+        ))
+    }
+}
@@ -0,0 +1,71 @@
+use crate::logs::Logger;
+use crate::response::{IntoResponse, Response, StatusCode};
+use crate::template::TemplateEngine;
+use crate::utils::get_content_type;
+use async_std::path::PathBuf;
+use async_std::task;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Renders the template `name` with `context`. The Content-Type is derived from the
+// template's extension, so `index.html` is sent as HTML and `robots.txt` as plain text.
+pub struct TemplateResponse<C> {
+    pub status_code: u16,
+    pub engine: Arc<dyn TemplateEngine>,
+    pub name: String,
+    pub context: C,
+    pub headers: Option<HashMap<String, String>>,
+}
+
+impl<C> TemplateResponse<C> {
+    pub fn new(engine: Arc<dyn TemplateEngine>, name: &str, context: C) -> TemplateResponse<C> {
+        TemplateResponse {
+            status_code: 200,
+            engine,
+            name: name.to_string(),
+            context,
+            headers: None,
+        }
+    }
+}
+
+impl<C: Serialize + Send> IntoResponse for TemplateResponse<C> {
+    async fn into_response(self) -> Response {
+        let rendered = match serde_json::to_value(&self.context) {
+            // Engines may read template files, e.g. `Templates` when hot reloading,
+            // so they don't run on the async executor
+            Ok(context) => {
+                let engine = self.engine.clone();
+                let name = self.name.clone();
+                task::spawn_blocking(move || engine.render(&name, &context))
+                    .await
+                    .map_err(|err| err.to_string())
+            }
+            Err(err) => Err(err.to_string()),
+        };
+        let body = match rendered {
+            Ok(body) => body,
+            Err(err) => {
+                Logger::new().error(&format!("Error rendering template: {}", err));
+                return Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .header("Content-Type", "text/plain; charset=utf-8")
+                    .body("500 Internal Server Error");
+            }
+        };
+
+        let content_type = match get_content_type(&PathBuf::from(&self.name)) {
+            "application/octet-stream" => "text/html",
+            content_type => content_type,
+        };
+        let mut headers = self.headers.unwrap_or_default();
+        headers.insert(
+            "Content-Type".to_string(),
+            format!("{}; charset=utf-8", content_type),
+        );
+        Response::new(self.status_code)
+            .with_body(body)
+            .with_headers(headers)
+    }
+}
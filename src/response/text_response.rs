@@ -0,0 +1,21 @@
+use crate::response::{IntoResponse, Response};
+use std::collections::HashMap;
+
+pub struct TextResponse {
+    pub status_code: u16,
+    pub content: String,
+    pub headers: Option<HashMap<String, String>>,
+}
+
+impl IntoResponse for TextResponse {
+    async fn into_response(self) -> Response {
+        let mut headers: HashMap<String, String> = self.headers.unwrap_or_default();
+        headers.insert(
+            "Content-Type".to_string(),
+            "text/plain; charset=utf-8".to_string(),
+        );
+        Response::new(self.status_code)
+            .with_body(self.content.into_bytes())
+            .with_headers(headers)
+    }
+}
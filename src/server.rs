@@ -2,23 +2,32 @@ use async_std::net::TcpListener;
 use async_std::net::TcpStream;
 use async_std::path::PathBuf;
 use async_std::prelude::*;
+use futures::future::LocalBoxFuture;
 use futures::StreamExt;
 use std::collections::HashMap;
 use std::pin::Pin;
 
 use crate::logs::Logger;
+use crate::middleware::{Middleware, MiddlewareStack};
 use crate::request::Method;
 use crate::request::Request;
+use crate::request::{ParseError, RequestConfig};
 
 use crate::response::file_response::FileResponse;
 use crate::response::IntoResponse;
 use crate::response::Response;
+use crate::response::StatusCode;
 
 pub struct Server {
     pub address: String,
     pub port: String,
     pub routes: HashMap<String, AsyncHandler>,
     pub static_dirs: HashMap<String, String>,
+    pub request_config: RequestConfig,
+    // Value of the `Server` header sent with every response, omitted if `None`
+    pub server_header: Option<String>,
+    // Runs around every request, including static files and 404s
+    pub middleware: MiddlewareStack,
     logger: Logger,
 }
 
@@ -61,6 +70,51 @@ macro_rules! headers {
     }};
 }
 
+// Routes sharing a path prefix and middleware.
+// Group middleware runs after server-wide middleware and before the route's own.
+//
+//     let group = RouteGroup::new("/api")
+//         .with_middleware(auth)
+//         .route(route!(GET, "/users", list_users))
+//         .route_with(route!(DELETE, "/users", delete_user), &MiddlewareStack::new().with(audit));
+//     server.register_group(group);
+pub struct RouteGroup {
+    prefix: String,
+    middleware: MiddlewareStack,
+    routes: Vec<(Method, String, AsyncHandler)>,
+}
+
+impl RouteGroup {
+    pub fn new(prefix: &str) -> RouteGroup {
+        RouteGroup {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            middleware: MiddlewareStack::new(),
+            routes: Vec::new(),
+        }
+    }
+
+    // Applies to every route of the group, including ones added before
+    pub fn with_middleware(mut self, middleware: impl Middleware + 'static) -> RouteGroup {
+        self.middleware.push(middleware);
+        self
+    }
+
+    pub fn route(mut self, data: (Method, &str, AsyncHandler)) -> RouteGroup {
+        let (method, path, handler) = data;
+        self.routes.push((method, path.to_string(), handler));
+        self
+    }
+
+    pub fn route_with(
+        self,
+        data: (Method, &str, AsyncHandler),
+        middleware: &MiddlewareStack,
+    ) -> RouteGroup {
+        let (method, path, handler) = data;
+        self.route((method, path, middleware.wrap(handler)))
+    }
+}
+
 impl Server {
     pub fn new(address: Option<String>, port: Option<String>) -> Server {
         Server {
@@ -69,9 +123,17 @@ impl Server {
             logger: Logger::new(),
             routes: HashMap::new(),
             static_dirs: HashMap::new(),
+            request_config: RequestConfig::default(),
+            server_header: None,
+            middleware: MiddlewareStack::new(),
         }
     }
 
+    // Server-wide middleware runs before any group or route middleware, in the order it was added
+    pub fn add_middleware(&mut self, middleware: impl Middleware + 'static) {
+        self.middleware.push(middleware);
+    }
+
     pub fn register_static_dir(&mut self, url_path: &str, dir_path: Option<&str>) {
         let dir_path = PathBuf::from(dir_path.unwrap_or(url_path));
         // Since we're in an async block, we need to lock asynchronously to modify shared state
@@ -83,10 +145,27 @@ impl Server {
 
     pub fn register_route(&mut self, data: (Method, &str, AsyncHandler)) {
         let (method, path, handler) = data;
-        let index = format!("{} {}", method.to_string(), path);
+        let index = format!("{} {}", method, path);
         self.routes.insert(index, handler);
     }
 
+    // Registers a route that runs `middleware` before its handler
+    pub fn register_route_with(
+        &mut self,
+        data: (Method, &str, AsyncHandler),
+        middleware: &MiddlewareStack,
+    ) {
+        let (method, path, handler) = data;
+        self.register_route((method, path, middleware.wrap(handler)));
+    }
+
+    pub fn register_group(&mut self, group: RouteGroup) {
+        for (method, path, handler) in group.routes {
+            let index = format!("{} {}{}", method, group.prefix, path);
+            self.routes.insert(index, group.middleware.wrap(handler));
+        }
+    }
+
     pub async fn listen(&self) {
         let listener = TcpListener::bind(format!("{}:{}", self.address, self.port)).await;
         let listener = listener.expect("[ERROR] Failed binding server to address. Exiting...");
@@ -121,15 +200,22 @@ impl Server {
     pub async fn handle_connection(&self, mut stream: TcpStream) {
         let logger = Logger::new();
         let mut request = Request::new();
-        if request.parse(&mut stream).await.is_err() {
-            logger.error("Error parsing request");
-            Response {
-                status_code: 400,
-                headers: headers!(("Content-Type", "text/plain")),
-                body: Some(b"400 Bad Request".to_vec()),
+        request.peer_addr = stream.peer_addr().ok();
+        if let Err(err) = request
+            .parse_with_config(&mut stream, &self.request_config)
+            .await
+        {
+            logger.error(&format!("Error parsing request: {}", err));
+            let status_code = err.status_code();
+            let mut response = Response::builder()
+                .status(status_code)
+                .header("Content-Type", "text/plain")
+                .body(status_code.to_string());
+            // Tells the client which encodings it could have used instead
+            if let ParseError::UnsupportedEncoding(_) = err {
+                response.set_header("Accept-Encoding", "gzip, deflate, br");
             }
-            .send(&mut stream)
-            .await;
+            self.send_response(response, &mut stream, false).await;
             return;
         }
 
@@ -143,35 +229,68 @@ impl Server {
                 .unwrap_or(&String::from("N/A"))
         ));
 
-        let route_index = format!("{} {}", request.method.as_str(), request.path.as_str());
+        let is_head = request.method == Method::HEAD.as_str();
+        let dispatch = |request: Request| -> LocalBoxFuture<'_, Response> {
+            Box::pin(self.dispatch(request))
+        };
+        let mut response = self.middleware.run(request, &dispatch).await;
+        let upgrade = response.upgrade.take();
+        let switching_protocols = response.status_code == StatusCode::SWITCHING_PROTOCOLS;
+        self.send_response(response, &mut stream, is_head).await;
+
+        // e.g. WebSockets, the protocol handler owns the connection from now on
+        if let (Some(upgrade), true) = (upgrade, switching_protocols) {
+            upgrade.run(stream).await;
+        }
+    }
+
+    async fn dispatch(&self, request: Request) -> Response {
+        // Routes are registered without query string, `Query<T>` reads it from the request
+        let path = request.path.split('?').next().unwrap_or_default();
+        let route_index = format!("{} {}", request.method.as_str(), path);
+        // HEAD requests are answered by the GET route unless there is a dedicated one
+        let get_index = format!("{} {}", Method::GET, path);
+        let is_head = request.method == Method::HEAD.as_str();
 
         // If not found in static_dirs, try to match in routes
-        if let Some(route) = self.routes.get(&route_index) {
-            route(request).await.send(&mut stream).await;
-            return;
+        let route = match self.routes.get(&route_index) {
+            Some(route) => Some(route),
+            None if is_head => self.routes.get(&get_index),
+            None => None,
+        };
+        if let Some(route) = route {
+            return route(request).await;
         }
 
         // If no route is found, return 404 Not Found
+        let static_index = if is_head { &get_index } else { &route_index };
         if let Some((request_url_path, dir_path)) = self
             .static_dirs
             .iter()
-            .find(|(url_path, _)| route_index.starts_with(url_path.as_str()))
+            .find(|(url_path, _)| static_index.starts_with(url_path.as_str()))
         {
-            let relative_path = route_index.trim_start_matches(request_url_path);
-            let relative_path = relative_path.split('?').next().unwrap_or(relative_path);
-            self.provide_static_dir(PathBuf::from(dir_path), relative_path.to_string())
-                .await
-                .send(&mut stream)
+            let relative_path = static_index.trim_start_matches(request_url_path);
+            return self
+                .provide_static_dir(PathBuf::from(dir_path), relative_path.to_string())
                 .await;
-            return;
         }
-        Response {
-            status_code: 400,
-            headers: headers!(("Content-Type", "text/plain")),
-            body: Some(b"404 Not Found".to_vec()),
+        Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "text/plain")
+            .body("404 Not Found")
+    }
+
+    async fn send_response(&self, mut response: Response, stream: &mut TcpStream, is_head: bool) {
+        if let Some(server_header) = &self.server_header {
+            if response.header("Server").is_none() {
+                response.set_header("Server", server_header);
+            }
+        }
+        if is_head {
+            response.send_head(stream).await;
+        } else {
+            response.send(stream).await;
         }
-        .send(&mut stream)
-        .await;
     }
 
     async fn provide_static_dir(
@@ -200,11 +319,10 @@ impl Server {
         }
 
         if !file_path.exists().await {
-            return Response {
-                status_code: 404,
-                headers: headers!(("Content-Type", "text/plain")),
-                body: Some(b"404 Not Found".to_vec()),
-            };
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .header("Content-Type", "text/plain")
+                .body("404 Not Found");
         }
 
         FileResponse {
@@ -216,3 +334,58 @@ impl Server {
         .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::form::Query;
+    use crate::request::Method::GET;
+    use crate::response::json_response::JsonResponse;
+    use serde_json::json;
+
+    async fn search(request: Request) -> Response {
+        let query = Query::<HashMap<String, String>>::from_request_checked(&request).unwrap();
+        JsonResponse {
+            status_code: 200,
+            headers: None,
+            body: json!(query.into_inner()),
+        }
+        .into_response()
+        .await
+    }
+
+    // Sends `raw` over a real connection and returns everything the server wrote back
+    async fn send(server: &Server, raw: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        client.write_all(raw.as_bytes()).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        server.handle_connection(stream).await;
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[async_std::test]
+    async fn matches_routes_without_the_query_string() {
+        let mut server = Server::new(None, None);
+        server.register_route(route!(GET, "/search", search));
+
+        let response = send(
+            &server,
+            "GET /search?q=surfer+rust&page=2 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with(r#"{"page":"2","q":"surfer rust"}"#));
+
+        let response = send(
+            &server,
+            "GET /searching?q=1 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .await;
+        assert!(response.ends_with("404 Not Found"), "{}", response);
+    }
+}
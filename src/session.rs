@@ -0,0 +1,497 @@
+pub mod file_store;
+pub mod memory_store;
+
+use crate::cookie::{Cookie, SameSite};
+use crate::logs::Logger;
+use crate::middleware::{Middleware, Next};
+use crate::request::{FromRequest, Request};
+use crate::response::{Response, StatusCode};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+pub use file_store::FileStore;
+pub use memory_store::MemoryStore;
+
+pub const DEFAULT_COOKIE_NAME: &str = "surfer_session";
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+pub const DEFAULT_ABSOLUTE_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+// What a store keeps per session ID
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub data: Map<String, Value>,
+    pub created_at: DateTime<Utc>,
+    pub accessed_at: DateTime<Utc>,
+    // Whichever of idle and absolute expiry comes first, stores may drop the record afterwards
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl SessionRecord {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+// Persists sessions between requests, see `MemoryStore` and `FileStore`
+pub trait SessionStore: Send + Sync {
+    // `None` if there is no session with this ID
+    fn load(&self, id: &str) -> impl Future<Output = io::Result<Option<SessionRecord>>> + Send;
+    fn store(
+        &self,
+        id: &str,
+        record: &SessionRecord,
+    ) -> impl Future<Output = io::Result<()>> + Send;
+    fn remove(&self, id: &str) -> impl Future<Output = io::Result<()>> + Send;
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    pub cookie_name: String,
+    pub cookie_path: String,
+    pub cookie_domain: Option<String>,
+    pub secure: bool,
+    pub same_site: SameSite,
+    // Sessions end after this long without a request
+    pub idle_timeout: Option<Duration>,
+    // Sessions end this long after they were created, no matter how active they are
+    pub absolute_timeout: Option<Duration>,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            cookie_name: DEFAULT_COOKIE_NAME.to_string(),
+            cookie_path: "/".to_string(),
+            cookie_domain: None,
+            secure: false,
+            same_site: SameSite::Lax,
+            idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+            absolute_timeout: Some(DEFAULT_ABSOLUTE_TIMEOUT),
+        }
+    }
+}
+
+// Data of the current session. Values are stored as JSON, either one by one with
+// `get`/`insert` or all at once as a typed struct with `typed`/`set_typed`.
+#[derive(Debug, Clone)]
+pub struct Session {
+    // `None` until the session has been saved for the first time
+    id: Option<String>,
+    data: Map<String, Value>,
+    created_at: DateTime<Utc>,
+    changed: bool,
+    rotate: bool,
+    destroyed: bool,
+}
+
+impl Session {
+    fn new() -> Session {
+        Session {
+            id: None,
+            data: Map::new(),
+            created_at: Utc::now(),
+            changed: false,
+            rotate: false,
+            destroyed: false,
+        }
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    pub fn is_new(&self) -> bool {
+        self.id.is_none()
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        serde_json::from_value(self.data.get(key)?.clone()).ok()
+    }
+
+    pub fn insert<T: Serialize>(&mut self, key: &str, value: T) -> serde_json::Result<()> {
+        self.data
+            .insert(key.to_string(), serde_json::to_value(value)?);
+        self.changed = true;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        let value = self.data.remove(key);
+        self.changed |= value.is_some();
+        value
+    }
+
+    // The whole session as `T`, `None` if it doesn't deserialize
+    pub fn typed<T: DeserializeOwned>(&self) -> Option<T> {
+        serde_json::from_value(Value::Object(self.data.clone())).ok()
+    }
+
+    // Replaces the whole session, `value` has to serialize to a map
+    pub fn set_typed<T: Serialize>(&mut self, value: &T) -> serde_json::Result<()> {
+        match serde_json::to_value(value)? {
+            Value::Object(data) => {
+                self.data = data;
+                self.changed = true;
+                Ok(())
+            }
+            _ => Err(serde::ser::Error::custom(
+                "sessions have to serialize to a map",
+            )),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.changed |= !self.data.is_empty();
+        self.data.clear();
+    }
+
+    // Issues a new ID while keeping the data. Call this whenever the privileges change,
+    // e.g. on login, so an ID the client had before can't be used to take over the session.
+    pub fn rotate_id(&mut self) {
+        self.rotate = true;
+    }
+
+    // Removes the session from the store and the cookie from the client, e.g. on logout
+    pub fn destroy(&mut self) {
+        self.destroyed = true;
+    }
+}
+
+// Session of the current request, available to handlers when `SessionLayer` is used as middleware
+#[derive(Debug, Clone)]
+pub struct SessionHandle(Arc<Mutex<Session>>);
+
+impl SessionHandle {
+    // Don't hold on to the guard across an `.await`
+    pub fn lock(&self) -> MutexGuard<'_, Session> {
+        self.0.lock().unwrap()
+    }
+}
+
+impl FromRequest for SessionHandle {
+    fn from_request(request: &Request) -> Result<Self, Response> {
+        request
+            .extensions
+            .get::<SessionHandle>()
+            .cloned()
+            .ok_or_else(|| {
+                Logger::new().error("No session found, is the SessionLayer middleware missing?");
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .header("Content-Type", "text/plain; charset=utf-8")
+                    .body("500 Internal Server Error")
+            })
+    }
+}
+
+// Loads the session of a request and saves it along with the response.
+// Either add it as middleware and get the session with `SessionHandle::from_request`,
+// or call it from a handler:
+//
+//     let mut session = sessions.load(&request).await;
+//     session.insert("user_id", 42)?;
+//     session.rotate_id();
+//     sessions.save(session, response).await
+pub struct SessionLayer<S> {
+    store: S,
+    config: SessionConfig,
+}
+
+impl<S: SessionStore> SessionLayer<S> {
+    pub fn new(store: S) -> SessionLayer<S> {
+        SessionLayer {
+            store,
+            config: SessionConfig::default(),
+        }
+    }
+
+    pub fn with_config(mut self, config: SessionConfig) -> SessionLayer<S> {
+        self.config = config;
+        self
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    // Unknown and expired sessions result in a new, empty session
+    pub async fn load(&self, request: &Request) -> Session {
+        let Some(id) = request
+            .cookies()
+            .into_iter()
+            .find(|cookie| cookie.name == self.config.cookie_name)
+            .map(|cookie| cookie.value)
+        else {
+            return Session::new();
+        };
+        let record = match self.store.load(&id).await {
+            Ok(Some(record)) => record,
+            Ok(None) => return Session::new(),
+            Err(err) => {
+                Logger::new().error(&format!("Error loading session: {}", err));
+                return Session::new();
+            }
+        };
+        if record.is_expired(Utc::now()) {
+            let _ = self.store.remove(&id).await;
+            return Session::new();
+        }
+        Session {
+            id: Some(id),
+            data: record.data,
+            created_at: record.created_at,
+            changed: false,
+            rotate: false,
+            destroyed: false,
+        }
+    }
+
+    // Stores the session and sets the session cookie if the ID changed.
+    // Sessions that were never written to are not stored, so visitors without one don't get a cookie.
+    pub async fn save(&self, session: Session, mut response: Response) -> Response {
+        if session.destroyed {
+            if let Some(id) = &session.id {
+                if let Err(err) = self.store.remove(id).await {
+                    return self.save_failed(err);
+                }
+                if let Err(err) = response.add_cookie(self.cookie(String::new()).into_removal()) {
+                    return self.save_failed(err);
+                }
+            }
+            return response;
+        }
+        if session.id.is_none() && !session.changed {
+            return response;
+        }
+
+        let new_id = match &session.id {
+            Some(old_id) if session.rotate => {
+                if let Err(err) = self.store.remove(old_id).await {
+                    return self.save_failed(err);
+                }
+                None
+            }
+            Some(id) => Some(id.clone()),
+            None => None,
+        };
+        let is_new_id = new_id.is_none();
+        let id = new_id.unwrap_or_else(generate_id);
+
+        // Storing unchanged sessions as well keeps the idle timeout from running out
+        let now = Utc::now();
+        let record = SessionRecord {
+            data: session.data,
+            created_at: session.created_at,
+            accessed_at: now,
+            expires_at: self.expires_at(session.created_at, now),
+        };
+        if let Err(err) = self.store.store(&id, &record).await {
+            return self.save_failed(err);
+        }
+        if is_new_id {
+            if let Err(err) = response.add_cookie(self.cookie(id)) {
+                return self.save_failed(err);
+            }
+        }
+        response
+    }
+
+    fn expires_at(&self, created_at: DateTime<Utc>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let after = |from: DateTime<Utc>, timeout: Option<Duration>| {
+            Some(from + chrono::Duration::from_std(timeout?).ok()?)
+        };
+        let idle = after(now, self.config.idle_timeout);
+        let absolute = after(created_at, self.config.absolute_timeout);
+        match (idle, absolute) {
+            (Some(idle), Some(absolute)) => Some(idle.min(absolute)),
+            (idle, absolute) => idle.or(absolute),
+        }
+    }
+
+    fn cookie(&self, id: String) -> Cookie {
+        let mut cookie = Cookie::new(&self.config.cookie_name, &id)
+            .with_path(&self.config.cookie_path)
+            .with_http_only(true)
+            .with_secure(self.config.secure)
+            .with_same_site(self.config.same_site);
+        cookie.domain = self.config.cookie_domain.clone();
+        cookie
+    }
+
+    fn save_failed(&self, err: impl fmt::Display) -> Response {
+        Logger::new().error(&format!("Error saving session: {}", err));
+        Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body("500 Internal Server Error")
+    }
+}
+
+// 256 random bits, URL-safe so it can be used as cookie value and file name
+fn generate_id() -> String {
+    let mut bytes = [0; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+impl<S: SessionStore> Middleware for SessionLayer<S> {
+    async fn handle(&self, mut request: Request, next: Next<'_>) -> Response {
+        let session = SessionHandle(Arc::new(Mutex::new(self.load(&request).await)));
+        request.extensions.insert(session.clone());
+        let response = next.run(request).await;
+        let session = session.lock().clone();
+        self.save(session, response).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Record with some data, for the store tests as well
+    pub(super) fn record(expires_at: Option<DateTime<Utc>>) -> SessionRecord {
+        let mut data = Map::new();
+        data.insert("user".to_string(), Value::from(42));
+        SessionRecord {
+            data,
+            created_at: Utc::now(),
+            accessed_at: Utc::now(),
+            expires_at,
+        }
+    }
+
+    fn minutes(minutes: i64) -> chrono::Duration {
+        chrono::Duration::try_minutes(minutes).unwrap()
+    }
+
+    fn request(session_id: Option<&str>) -> Request {
+        let mut request = Request::new();
+        if let Some(id) = session_id {
+            request.headers.insert(
+                "Cookie".to_string(),
+                format!("other=1; {}={}", DEFAULT_COOKIE_NAME, id),
+            );
+        }
+        request
+    }
+
+    // Saves a session with `user` set and returns the ID of the cookie that was issued
+    async fn logged_in(layer: &SessionLayer<MemoryStore>) -> String {
+        let mut session = layer.load(&request(None)).await;
+        session.insert("user", 42).unwrap();
+        let response = layer.save(session, Response::new(200)).await;
+        response.cookies[0].value.clone()
+    }
+
+    #[async_std::test]
+    async fn untouched_new_sessions_get_no_cookie() {
+        let layer = SessionLayer::new(MemoryStore::new());
+        let session = layer.load(&request(None)).await;
+        assert!(session.is_new());
+        let response = layer.save(session, Response::new(200)).await;
+        assert!(response.cookies.is_empty());
+        assert!(layer.store().is_empty());
+    }
+
+    #[async_std::test]
+    async fn changed_sessions_are_stored_and_loaded() {
+        let layer = SessionLayer::new(MemoryStore::new());
+        let mut session = layer.load(&request(None)).await;
+        session.insert("user", 42).unwrap();
+        let response = layer.save(session, Response::new(200)).await;
+        let cookie = &response.cookies[0];
+        assert_eq!(cookie.name, DEFAULT_COOKIE_NAME);
+        assert_eq!(cookie.http_only, Some(true));
+        assert_eq!(cookie.path.as_deref(), Some("/"));
+
+        let session = layer.load(&request(Some(&cookie.value))).await;
+        assert_eq!(session.id(), Some(cookie.value.as_str()));
+        assert_eq!(session.get::<u32>("user"), Some(42));
+        // The client already has the cookie
+        let response = layer.save(session, Response::new(200)).await;
+        assert!(response.cookies.is_empty());
+
+        let session = layer.load(&request(Some("unknown"))).await;
+        assert!(session.is_new());
+    }
+
+    #[async_std::test]
+    async fn rotate_id_replaces_the_old_id() {
+        let layer = SessionLayer::new(MemoryStore::new());
+        let old_id = logged_in(&layer).await;
+
+        let mut session = layer.load(&request(Some(&old_id))).await;
+        session.rotate_id();
+        let response = layer.save(session, Response::new(200)).await;
+        let new_id = &response.cookies[0].value;
+        assert_ne!(new_id, &old_id);
+
+        assert!(layer.store().load(&old_id).await.unwrap().is_none());
+        assert!(layer.load(&request(Some(&old_id))).await.is_new());
+        let session = layer.load(&request(Some(new_id))).await;
+        assert_eq!(session.get::<u32>("user"), Some(42));
+    }
+
+    #[async_std::test]
+    async fn destroy_removes_the_session_and_cookie() {
+        let layer = SessionLayer::new(MemoryStore::new());
+        let id = logged_in(&layer).await;
+
+        let mut session = layer.load(&request(Some(&id))).await;
+        session.destroy();
+        let response = layer.save(session, Response::new(200)).await;
+        let cookie = &response.cookies[0];
+        assert_eq!(cookie.name, DEFAULT_COOKIE_NAME);
+        assert_eq!(cookie.value, "");
+        assert_eq!(cookie.max_age, Some(0));
+        assert!(layer.store().is_empty());
+    }
+
+    #[async_std::test]
+    async fn expired_records_are_dropped_on_load() {
+        let layer = SessionLayer::new(MemoryStore::new());
+        let store = layer.store();
+        store
+            .store("expired", &record(Some(Utc::now() - minutes(1))))
+            .await
+            .unwrap();
+
+        let session = layer.load(&request(Some("expired"))).await;
+        assert!(session.is_new());
+        assert_eq!(session.get::<u32>("user"), None);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn expiry_is_the_earlier_of_idle_and_absolute() {
+        let layer = SessionLayer::new(MemoryStore::new()).with_config(SessionConfig {
+            idle_timeout: Some(Duration::from_secs(30 * 60)),
+            absolute_timeout: Some(Duration::from_secs(60 * 60)),
+            ..SessionConfig::default()
+        });
+        let now = Utc::now();
+        assert_eq!(layer.expires_at(now, now), Some(now + minutes(30)));
+        let created_at = now - minutes(50);
+        assert_eq!(
+            layer.expires_at(created_at, now),
+            Some(created_at + minutes(60))
+        );
+
+        let layer = SessionLayer::new(MemoryStore::new()).with_config(SessionConfig {
+            idle_timeout: None,
+            absolute_timeout: None,
+            ..SessionConfig::default()
+        });
+        assert_eq!(layer.expires_at(now, now), None);
+    }
+}
@@ -0,0 +1,175 @@
+use crate::session::{SessionRecord, SessionStore};
+use async_std::fs;
+use async_std::path::{Path, PathBuf};
+use chrono::Utc;
+use futures::StreamExt;
+use rand::RngCore;
+use std::io;
+
+const EXTENSION: &str = "session";
+
+// Keeps every session as a JSON file in `dir`, so sessions survive restarts
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(dir: impl AsRef<Path>) -> FileStore {
+        FileStore {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    // IDs come from cookies, anything that isn't a generated ID must not end up in a path
+    fn path(&self, id: &str) -> io::Result<PathBuf> {
+        let valid = !id.is_empty()
+            && id
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_');
+        if !valid {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid session ID",
+            ));
+        }
+        Ok(self.dir.join(format!("{}.{}", id, EXTENSION)))
+    }
+
+    // Deletes the files of expired sessions, e.g. from a periodic task
+    pub async fn remove_expired(&self) -> io::Result<()> {
+        let now = Utc::now();
+        let mut entries = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next().await {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(EXTENSION) {
+                continue;
+            }
+            let expired = match fs::read(&path).await {
+                Ok(contents) => serde_json::from_slice::<SessionRecord>(&contents)
+                    .map_or(true, |record| record.is_expired(now)),
+                Err(_) => continue,
+            };
+            if expired {
+                let _ = fs::remove_file(&path).await;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SessionStore for FileStore {
+    async fn load(&self, id: &str) -> io::Result<Option<SessionRecord>> {
+        let Ok(path) = self.path(id) else {
+            return Ok(None);
+        };
+        let contents = match fs::read(&path).await {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        serde_json::from_slice(&contents)
+            .map(Some)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    async fn store(&self, id: &str, record: &SessionRecord) -> io::Result<()> {
+        let path = self.path(id)?;
+        fs::create_dir_all(&self.dir).await?;
+        let contents = serde_json::to_vec(record)?;
+        // Written to a temporary file first, so concurrent loads never see half a session.
+        // Every write gets its own, concurrent stores of the same session would clash otherwise.
+        let temp_path = self.dir.join(format!(
+            ".{}.{:016x}.tmp",
+            id,
+            rand::thread_rng().next_u64()
+        ));
+        if let Err(err) = fs::write(&temp_path, contents).await {
+            let _ = fs::remove_file(&temp_path).await;
+            return Err(err);
+        }
+        fs::rename(&temp_path, &path).await
+    }
+
+    async fn remove(&self, id: &str) -> io::Result<()> {
+        let Ok(path) = self.path(id) else {
+            return Ok(());
+        };
+        match fs::remove_file(path).await {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::tests::record;
+    use chrono::Duration;
+
+    fn store() -> FileStore {
+        let dir = std::env::temp_dir().join(format!(
+            "surfer-sessions-{:016x}",
+            rand::thread_rng().next_u64()
+        ));
+        FileStore::new(dir)
+    }
+
+    async fn file_names(store: &FileStore) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut entries = fs::read_dir(&store.dir).await.unwrap();
+        while let Some(entry) = entries.next().await {
+            names.push(entry.unwrap().file_name().to_string_lossy().to_string());
+        }
+        names.sort();
+        names
+    }
+
+    #[async_std::test]
+    async fn stores_loads_and_removes() {
+        let store = store();
+        store.store("abc", &record(None)).await.unwrap();
+        let loaded = store.load("abc").await.unwrap().unwrap();
+        assert_eq!(loaded.data["user"], 42);
+        assert_eq!(file_names(&store).await, ["abc.session"]);
+
+        store.remove("abc").await.unwrap();
+        assert!(store.load("abc").await.unwrap().is_none());
+        let _ = fs::remove_dir_all(&store.dir).await;
+    }
+
+    #[async_std::test]
+    async fn ignores_ids_that_are_no_file_names() {
+        let store = store();
+        assert!(store.store("../abc", &record(None)).await.is_err());
+        assert!(store.load("../abc").await.unwrap().is_none());
+        assert!(store.load("a/b").await.unwrap().is_none());
+    }
+
+    #[async_std::test]
+    async fn concurrent_stores_of_a_session_succeed() {
+        let store = store();
+        let stores = (0..16).map(|_| {
+            let store = store.clone();
+            async_std::task::spawn(async move { store.store("abc", &record(None)).await })
+        });
+        for result in futures::future::join_all(stores).await {
+            result.unwrap();
+        }
+        assert_eq!(file_names(&store).await, ["abc.session"]);
+        let _ = fs::remove_dir_all(&store.dir).await;
+    }
+
+    #[async_std::test]
+    async fn remove_expired_deletes_expired_files() {
+        let store = store();
+        let past = Utc::now() - Duration::try_minutes(1).unwrap();
+        store.store("expired", &record(Some(past))).await.unwrap();
+        store.store("live", &record(None)).await.unwrap();
+
+        store.remove_expired().await.unwrap();
+        assert_eq!(file_names(&store).await, ["live.session"]);
+        let _ = fs::remove_dir_all(&store.dir).await;
+    }
+}
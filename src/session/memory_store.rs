@@ -0,0 +1,116 @@
+use crate::session::{SessionRecord, SessionStore};
+use chrono::Utc;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+// Keeps sessions in memory, they are lost on restart. Clones share the same sessions.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryState {
+    sessions: HashMap<String, SessionRecord>,
+    // Expired sessions are dropped once the map grows this large
+    next_cleanup: usize,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn remove_expired(&self) {
+        let now = Utc::now();
+        self.state
+            .lock()
+            .unwrap()
+            .sessions
+            .retain(|_, record| !record.is_expired(now));
+    }
+}
+
+impl SessionStore for MemoryStore {
+    async fn load(&self, id: &str) -> io::Result<Option<SessionRecord>> {
+        Ok(self.state.lock().unwrap().sessions.get(id).cloned())
+    }
+
+    async fn store(&self, id: &str, record: &SessionRecord) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        // Expired sessions would pile up otherwise. Waiting for the map to double keeps
+        // the cost per store constant.
+        if state.sessions.len() >= state.next_cleanup {
+            let now = Utc::now();
+            state.sessions.retain(|_, record| !record.is_expired(now));
+            state.next_cleanup = (state.sessions.len() * 2).max(1024);
+        }
+        state.sessions.insert(id.to_string(), record.clone());
+        Ok(())
+    }
+
+    async fn remove(&self, id: &str) -> io::Result<()> {
+        self.state.lock().unwrap().sessions.remove(id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::tests::record;
+    use chrono::Duration;
+
+    #[async_std::test]
+    async fn stores_loads_and_removes() {
+        let store = MemoryStore::new();
+        store.store("a", &record(None)).await.unwrap();
+        let loaded = store.load("a").await.unwrap().unwrap();
+        assert_eq!(loaded.data["user"], 42);
+
+        // Clones share the sessions
+        store.clone().remove("a").await.unwrap();
+        assert!(store.load("a").await.unwrap().is_none());
+        assert!(store.is_empty());
+    }
+
+    #[async_std::test]
+    async fn remove_expired_keeps_live_sessions() {
+        let store = MemoryStore::new();
+        let past = Utc::now() - Duration::try_minutes(1).unwrap();
+        let future = Utc::now() + Duration::try_minutes(1).unwrap();
+        store.store("expired", &record(Some(past))).await.unwrap();
+        store.store("live", &record(Some(future))).await.unwrap();
+        store.store("forever", &record(None)).await.unwrap();
+
+        store.remove_expired();
+        assert_eq!(store.len(), 2);
+        assert!(store.load("expired").await.unwrap().is_none());
+    }
+
+    #[async_std::test]
+    async fn store_drops_expired_sessions_once_the_map_grows() {
+        let store = MemoryStore::new();
+        let past = Utc::now() - Duration::try_minutes(1).unwrap();
+        for index in 0..1024 {
+            store
+                .store(&format!("expired-{}", index), &record(Some(past)))
+                .await
+                .unwrap();
+        }
+        // The first store cleaned up the empty map, the next cleanup is due at 1024 sessions
+        assert_eq!(store.len(), 1024);
+
+        store.store("live", &record(None)).await.unwrap();
+        assert_eq!(store.len(), 1);
+    }
+}
@@ -0,0 +1,1153 @@
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+// Includes nested deeper than this are most likely including themselves
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+// Renders named templates, see `Templates` for the built-in engine.
+// The context is whatever the handler passed in, serialized to JSON.
+pub trait TemplateEngine: Send + Sync {
+    fn render(&self, name: &str, context: &Value) -> Result<String, TemplateError>;
+}
+
+#[derive(Debug)]
+pub enum TemplateError {
+    NotFound(String),
+    Io {
+        name: String,
+        error: io::Error,
+    },
+    Syntax {
+        name: String,
+        line: usize,
+        message: String,
+    },
+    Render {
+        name: String,
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::NotFound(name) => write!(f, "template '{}' not found", name),
+            TemplateError::Io { name, error } => {
+                write!(f, "failed to read template '{}': {}", name, error)
+            }
+            TemplateError::Syntax {
+                name,
+                line,
+                message,
+            } => write!(
+                f,
+                "syntax error in '{}' at line {}: {}",
+                name, line, message
+            ),
+            TemplateError::Render {
+                name,
+                line,
+                message,
+            } => write!(
+                f,
+                "failed to render '{}' at line {}: {}",
+                name, line, message
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TemplateError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+// The built-in template engine. The syntax is a small subset of Jinja:
+//
+//     {{ user.name }}                  output, HTML-escaped
+//     {{ post.body | safe }}           output without escaping
+//     {{ title | upper }}              filters: safe, upper, lower, trim, length, json
+//     {% if user and not user.banned %} .. {% elif x == 1 %} .. {% else %} .. {% endif %}
+//     {% for item in items %} {{ loop.index }} .. {% else %} empty .. {% endfor %}
+//     {% for key, value in map %} .. {% endfor %}
+//     {% include "partials/header.html" %}
+//     {# comment #}
+//
+// Templates loaded with `from_dir` are reloaded whenever their file changes in debug builds.
+// Release builds parse every template once on startup and never touch the files again.
+pub struct Templates {
+    dir: Option<PathBuf>,
+    hot_reload: bool,
+    templates: RwLock<HashMap<String, Template>>,
+}
+
+#[derive(Clone)]
+struct Template {
+    nodes: Arc<Vec<Node>>,
+    // Modification time of the file the template was loaded from
+    modified: Option<SystemTime>,
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        Templates::new()
+    }
+}
+
+impl Templates {
+    // Engine without a template directory, templates are added with `add_template`
+    pub fn new() -> Templates {
+        Templates {
+            dir: None,
+            hot_reload: false,
+            templates: RwLock::new(HashMap::new()),
+        }
+    }
+
+    // Loads every file below `dir`, named by its path relative to `dir`, e.g. `partials/nav.html`
+    pub fn from_dir(dir: impl AsRef<Path>) -> Result<Templates, TemplateError> {
+        let dir = dir.as_ref().to_path_buf();
+        let templates = Templates {
+            dir: Some(dir.clone()),
+            hot_reload: cfg!(debug_assertions),
+            templates: RwLock::new(HashMap::new()),
+        };
+        let mut names = Vec::new();
+        collect_files(&dir, &dir, &mut names).map_err(|error| TemplateError::Io {
+            name: dir.to_string_lossy().to_string(),
+            error,
+        })?;
+        for name in names {
+            templates.load(&name)?;
+        }
+        Ok(templates)
+    }
+
+    // Overrides whether changed template files are picked up while running
+    pub fn with_hot_reload(mut self, hot_reload: bool) -> Templates {
+        self.hot_reload = hot_reload;
+        self
+    }
+
+    pub fn add_template(&self, name: &str, source: &str) -> Result<(), TemplateError> {
+        let nodes = parse(name, source)?;
+        self.templates.write().unwrap().insert(
+            name.to_string(),
+            Template {
+                nodes: Arc::new(nodes),
+                modified: None,
+            },
+        );
+        Ok(())
+    }
+
+    fn load(&self, name: &str) -> Result<Template, TemplateError> {
+        let path = self
+            .path(name)
+            .ok_or_else(|| TemplateError::NotFound(name.to_string()))?;
+        let io_error = |error: io::Error| match error.kind() {
+            io::ErrorKind::NotFound => TemplateError::NotFound(name.to_string()),
+            _ => TemplateError::Io {
+                name: name.to_string(),
+                error,
+            },
+        };
+        let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+        let source = fs::read_to_string(&path).map_err(io_error)?;
+        let template = Template {
+            nodes: Arc::new(parse(name, &source)?),
+            modified,
+        };
+        self.templates
+            .write()
+            .unwrap()
+            .insert(name.to_string(), template.clone());
+        Ok(template)
+    }
+
+    // Path of the template file, `None` if the name would point outside the directory
+    fn path(&self, name: &str) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+        let escapes = name
+            .split(['/', '\\'])
+            .any(|segment| segment == ".." || segment.contains(':'));
+        if escapes || name.starts_with(['/', '\\']) {
+            return None;
+        }
+        Some(dir.join(name))
+    }
+
+    fn get(&self, name: &str) -> Result<Template, TemplateError> {
+        let loaded = self.templates.read().unwrap().get(name).cloned();
+        match loaded {
+            Some(template) if !self.hot_reload || template.modified.is_none() => Ok(template),
+            Some(template) => {
+                let path = self.path(name);
+                let modified = path.and_then(|path| fs::metadata(path).ok()?.modified().ok());
+                if modified.is_some() && modified != template.modified {
+                    self.load(name)
+                } else {
+                    Ok(template)
+                }
+            }
+            // New files show up without a restart while hot reloading
+            None if self.hot_reload => self.load(name),
+            None => Err(TemplateError::NotFound(name.to_string())),
+        }
+    }
+}
+
+fn collect_files(root: &Path, dir: &Path, names: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(root, &path, names)?;
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            let segments: Vec<String> = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy().to_string())
+                .collect();
+            names.push(segments.join("/"));
+        }
+    }
+    Ok(())
+}
+
+impl TemplateEngine for Templates {
+    fn render(&self, name: &str, context: &Value) -> Result<String, TemplateError> {
+        let mut renderer = Renderer {
+            templates: self,
+            context,
+            locals: Vec::new(),
+            output: String::new(),
+        };
+        renderer.render_template(name, 0)?;
+        Ok(renderer.output)
+    }
+}
+
+// Escapes text for use in HTML content and attribute values
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Output {
+        expr: Expr,
+        line: usize,
+    },
+    If {
+        branches: Vec<(Expr, Vec<Node>)>,
+        otherwise: Vec<Node>,
+    },
+    For {
+        key: Option<String>,
+        value: String,
+        iterable: Expr,
+        body: Vec<Node>,
+        empty: Vec<Node>,
+        line: usize,
+    },
+    Include {
+        name: String,
+        line: usize,
+    },
+}
+
+#[derive(Debug)]
+enum Expr {
+    Literal(Value),
+    Path(Vec<String>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(Box<Expr>, CompareOp, Box<Expr>),
+    Filter(Box<Expr>, Filter),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Filter {
+    Safe,
+    Upper,
+    Lower,
+    Trim,
+    Length,
+    Json,
+}
+
+enum Token<'a> {
+    Text(&'a str),
+    Output(&'a str, usize),
+    Tag(&'a str, usize),
+}
+
+fn tokenize<'a>(name: &str, source: &'a str) -> Result<Vec<Token<'a>>, TemplateError> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    let mut line = 1;
+    while !rest.is_empty() {
+        // Text up to the next delimiter
+        let next = ["{{", "{%", "{#"]
+            .iter()
+            .filter_map(|open| rest.find(open))
+            .min();
+        let Some(next) = next else {
+            tokens.push(Token::Text(rest));
+            break;
+        };
+        if next > 0 {
+            tokens.push(Token::Text(&rest[..next]));
+            line += rest[..next].matches('\n').count();
+        }
+        let close = match &rest[next..next + 2] {
+            "{{" => "}}",
+            "{%" => "%}",
+            _ => "#}",
+        };
+        let Some(end) = rest[next + 2..].find(close) else {
+            return Err(syntax_error(
+                name,
+                line,
+                format!("unclosed '{}'", &rest[next..next + 2]),
+            ));
+        };
+        let inner = &rest[next + 2..next + 2 + end];
+        match close {
+            "}}" => tokens.push(Token::Output(inner.trim(), line)),
+            "%}" => tokens.push(Token::Tag(inner.trim(), line)),
+            _ => {}
+        }
+        line += inner.matches('\n').count();
+        rest = &rest[next + 2 + end + 2..];
+    }
+    Ok(tokens)
+}
+
+fn syntax_error(name: &str, line: usize, message: impl Into<String>) -> TemplateError {
+    TemplateError::Syntax {
+        name: name.to_string(),
+        line,
+        message: message.into(),
+    }
+}
+
+fn parse(name: &str, source: &str) -> Result<Vec<Node>, TemplateError> {
+    let tokens = tokenize(name, source)?;
+    let mut parser = Parser {
+        name,
+        tokens,
+        position: 0,
+    };
+    let (nodes, end) = parser.parse_block(&[])?;
+    if let Some((tag, line)) = end {
+        return Err(syntax_error(name, line, format!("unexpected '{}'", tag)));
+    }
+    Ok(nodes)
+}
+
+struct Parser<'a> {
+    name: &'a str,
+    tokens: Vec<Token<'a>>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    // Parses nodes until one of the `end` tags, which is returned along with its line
+    #[allow(clippy::type_complexity)]
+    fn parse_block(
+        &mut self,
+        end: &[&str],
+    ) -> Result<(Vec<Node>, Option<(&'a str, usize)>), TemplateError> {
+        let mut nodes = Vec::new();
+        while self.position < self.tokens.len() {
+            let token = &self.tokens[self.position];
+            self.position += 1;
+            match *token {
+                Token::Text(text) => nodes.push(Node::Text(text.to_string())),
+                Token::Output(expr, line) => nodes.push(Node::Output {
+                    expr: self.parse_expr(expr, line)?,
+                    line,
+                }),
+                Token::Tag(tag, line) => {
+                    let keyword = tag.split_whitespace().next().unwrap_or_default();
+                    if end.contains(&keyword) {
+                        return Ok((nodes, Some((tag, line))));
+                    }
+                    let arguments = tag[keyword.len()..].trim();
+                    match keyword {
+                        "if" => nodes.push(self.parse_if(arguments, line)?),
+                        "for" => nodes.push(self.parse_for(arguments, line)?),
+                        "include" => {
+                            let name = arguments
+                                .strip_prefix('"')
+                                .and_then(|name| name.strip_suffix('"'))
+                                .or_else(|| {
+                                    arguments
+                                        .strip_prefix('\'')
+                                        .and_then(|name| name.strip_suffix('\''))
+                                })
+                                .ok_or_else(|| {
+                                    syntax_error(
+                                        self.name,
+                                        line,
+                                        "include expects a quoted template name",
+                                    )
+                                })?;
+                            nodes.push(Node::Include {
+                                name: name.to_string(),
+                                line,
+                            });
+                        }
+                        _ => {
+                            return Err(syntax_error(
+                                self.name,
+                                line,
+                                format!("unexpected '{}'", tag),
+                            ))
+                        }
+                    }
+                }
+            }
+        }
+        Ok((nodes, None))
+    }
+
+    fn expect_end(
+        &self,
+        end: Option<(&'a str, usize)>,
+        opening: &str,
+        line: usize,
+    ) -> Result<(&'a str, usize), TemplateError> {
+        end.ok_or_else(|| syntax_error(self.name, line, format!("unclosed '{}'", opening)))
+    }
+
+    fn parse_if(&mut self, condition: &str, line: usize) -> Result<Node, TemplateError> {
+        let mut branches = Vec::new();
+        let mut condition = self.parse_expr(condition, line)?;
+        loop {
+            let (body, end) = self.parse_block(&["elif", "else", "endif"])?;
+            let (tag, tag_line) = self.expect_end(end, "if", line)?;
+            branches.push((condition, body));
+            match tag.split_whitespace().next() {
+                Some("elif") => condition = self.parse_expr(tag[4..].trim(), tag_line)?,
+                Some("else") => {
+                    let (otherwise, end) = self.parse_block(&["endif"])?;
+                    self.expect_end(end, "if", line)?;
+                    return Ok(Node::If {
+                        branches,
+                        otherwise,
+                    });
+                }
+                _ => {
+                    return Ok(Node::If {
+                        branches,
+                        otherwise: Vec::new(),
+                    })
+                }
+            }
+        }
+    }
+
+    fn parse_for(&mut self, arguments: &str, line: usize) -> Result<Node, TemplateError> {
+        let (variables, iterable) = arguments
+            .split_once(" in ")
+            .ok_or_else(|| syntax_error(self.name, line, "expected 'for <name> in <value>'"))?;
+        let variables: Vec<&str> = variables.split(',').map(str::trim).collect();
+        let (key, value) = match variables[..] {
+            [value] => (None, value),
+            [key, value] => (Some(key.to_string()), value),
+            _ => return Err(syntax_error(self.name, line, "too many loop variables")),
+        };
+        let is_identifier =
+            |name: &str| !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+        if !is_identifier(value) || !key.as_deref().is_none_or(is_identifier) {
+            return Err(syntax_error(self.name, line, "invalid loop variable"));
+        }
+        let iterable = self.parse_expr(iterable, line)?;
+
+        let (body, end) = self.parse_block(&["else", "endfor"])?;
+        let (tag, _) = self.expect_end(end, "for", line)?;
+        let empty = if tag == "else" {
+            let (empty, end) = self.parse_block(&["endfor"])?;
+            self.expect_end(end, "for", line)?;
+            empty
+        } else {
+            Vec::new()
+        };
+        Ok(Node::For {
+            key,
+            value: value.to_string(),
+            iterable,
+            body,
+            empty,
+            line,
+        })
+    }
+
+    fn parse_expr(&self, source: &str, line: usize) -> Result<Expr, TemplateError> {
+        let tokens =
+            tokenize_expr(source).map_err(|message| syntax_error(self.name, line, message))?;
+        let mut parser = ExprParser {
+            tokens,
+            position: 0,
+        };
+        let expr = parser
+            .parse_or()
+            .map_err(|message| syntax_error(self.name, line, message))?;
+        if let Some(token) = parser.tokens.get(parser.position) {
+            return Err(syntax_error(
+                self.name,
+                line,
+                format!("unexpected '{}' in expression", token),
+            ));
+        }
+        Ok(expr)
+    }
+}
+
+fn tokenize_expr(source: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut literal = String::from('"');
+            loop {
+                match chars.next() {
+                    Some(next) if next == c => break,
+                    Some(next) => literal.push(next),
+                    None => return Err("unterminated string".to_string()),
+                }
+            }
+            tokens.push(literal);
+        } else if "=!<>".contains(c) {
+            chars.next();
+            let mut operator = c.to_string();
+            if chars.peek() == Some(&'=') {
+                operator.push('=');
+                chars.next();
+            }
+            tokens.push(operator);
+        } else if c == '|' || c == '(' || c == ')' {
+            chars.next();
+            tokens.push(c.to_string());
+        } else if c.is_alphanumeric() || c == '_' || c == '.' || c == '-' {
+            let mut word = String::new();
+            while let Some(&next) = chars.peek() {
+                if next.is_alphanumeric() || next == '_' || next == '.' || next == '-' {
+                    word.push(next);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            tokens.push(word);
+        } else {
+            return Err(format!("unexpected '{}' in expression", c));
+        }
+    }
+    Ok(tokens)
+}
+
+struct ExprParser {
+    tokens: Vec<String>,
+    position: usize,
+}
+
+impl ExprParser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_and()?;
+        while self.peek() == Some("or") {
+            self.position += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_not()?;
+        while self.peek() == Some("and") {
+            self.position += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_not()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, String> {
+        if self.peek() == Some("not") {
+            self.position += 1;
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_compare()
+    }
+
+    fn parse_compare(&mut self) -> Result<Expr, String> {
+        let left = self.parse_filtered()?;
+        let operator = match self.peek() {
+            Some("==") => CompareOp::Eq,
+            Some("!=") => CompareOp::Ne,
+            Some("<") => CompareOp::Lt,
+            Some("<=") => CompareOp::Le,
+            Some(">") => CompareOp::Gt,
+            Some(">=") => CompareOp::Ge,
+            _ => return Ok(left),
+        };
+        self.position += 1;
+        let right = self.parse_filtered()?;
+        Ok(Expr::Compare(Box::new(left), operator, Box::new(right)))
+    }
+
+    fn parse_filtered(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_primary()?;
+        while self.peek() == Some("|") {
+            self.position += 1;
+            let filter = match self.next().as_deref() {
+                Some("safe") => Filter::Safe,
+                Some("upper") => Filter::Upper,
+                Some("lower") => Filter::Lower,
+                Some("trim") => Filter::Trim,
+                Some("length") => Filter::Length,
+                Some("json") => Filter::Json,
+                Some(filter) => return Err(format!("unknown filter '{}'", filter)),
+                None => return Err("expected a filter after '|'".to_string()),
+            };
+            expr = Expr::Filter(Box::new(expr), filter);
+        }
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        let token = self
+            .next()
+            .ok_or_else(|| "expected an expression".to_string())?;
+        if token == "(" {
+            let expr = self.parse_or()?;
+            if self.next().as_deref() != Some(")") {
+                return Err("expected ')'".to_string());
+            }
+            return Ok(expr);
+        }
+        if let Some(literal) = token.strip_prefix('"') {
+            return Ok(Expr::Literal(Value::String(literal.to_string())));
+        }
+        match token.as_str() {
+            "true" => return Ok(Expr::Literal(Value::Bool(true))),
+            "false" => return Ok(Expr::Literal(Value::Bool(false))),
+            "none" | "null" => return Ok(Expr::Literal(Value::Null)),
+            _ => {}
+        }
+        if let Ok(number) = token.parse::<i64>() {
+            return Ok(Expr::Literal(Value::from(number)));
+        }
+        if let Ok(number) = token.parse::<f64>() {
+            return Ok(Expr::Literal(Value::from(number)));
+        }
+        let segments: Vec<String> = token.split('.').map(str::to_string).collect();
+        if segments.iter().any(|segment| segment.is_empty()) {
+            return Err(format!("unexpected '{}' in expression", token));
+        }
+        Ok(Expr::Path(segments))
+    }
+}
+
+struct Renderer<'a> {
+    templates: &'a Templates,
+    context: &'a Value,
+    // Loop variables, innermost last
+    locals: Vec<(String, Value)>,
+    output: String,
+}
+
+impl Renderer<'_> {
+    fn render_template(&mut self, name: &str, depth: usize) -> Result<(), TemplateError> {
+        let template = self.templates.get(name)?;
+        self.render_nodes(name, &template.nodes, depth)
+    }
+
+    fn render_nodes(
+        &mut self,
+        name: &str,
+        nodes: &[Node],
+        depth: usize,
+    ) -> Result<(), TemplateError> {
+        let render_error = |line: usize, message: String| TemplateError::Render {
+            name: name.to_string(),
+            line,
+            message,
+        };
+        for node in nodes {
+            match node {
+                Node::Text(text) => self.output.push_str(text),
+                Node::Output { expr, line } => {
+                    let value = self
+                        .evaluate(expr)
+                        .map_err(|message| render_error(*line, message))?;
+                    let text = to_text(&value);
+                    if is_safe(expr) {
+                        self.output.push_str(&text);
+                    } else {
+                        self.output.push_str(&escape_html(&text));
+                    }
+                }
+                Node::If {
+                    branches,
+                    otherwise,
+                } => {
+                    let branch = branches
+                        .iter()
+                        .find(|(condition, _)| self.is_true(condition))
+                        .map(|(_, body)| body)
+                        .unwrap_or(otherwise);
+                    self.render_nodes(name, branch, depth)?;
+                }
+                Node::For {
+                    key,
+                    value,
+                    iterable,
+                    body,
+                    empty,
+                    line,
+                } => {
+                    let items: Vec<(Value, Value)> = match self.lookup(iterable) {
+                        Some(Value::Array(items)) => items
+                            .into_iter()
+                            .enumerate()
+                            .map(|(index, item)| (Value::from(index), item))
+                            .collect(),
+                        Some(Value::Object(map)) => map
+                            .into_iter()
+                            .map(|(key, item)| (Value::String(key), item))
+                            .collect(),
+                        Some(Value::Null) | None => Vec::new(),
+                        Some(other) => {
+                            return Err(render_error(
+                                *line,
+                                format!("cannot iterate over {}", type_name(&other)),
+                            ))
+                        }
+                    };
+                    if items.is_empty() {
+                        self.render_nodes(name, empty, depth)?;
+                        continue;
+                    }
+                    let length = items.len();
+                    for (index, (item_key, item)) in items.into_iter().enumerate() {
+                        let mut loop_info = Map::new();
+                        loop_info.insert("index".to_string(), Value::from(index + 1));
+                        loop_info.insert("index0".to_string(), Value::from(index));
+                        loop_info.insert("first".to_string(), Value::Bool(index == 0));
+                        loop_info.insert("last".to_string(), Value::Bool(index + 1 == length));
+                        loop_info.insert("length".to_string(), Value::from(length));
+                        let locals = self.locals.len();
+                        self.locals
+                            .push(("loop".to_string(), Value::Object(loop_info)));
+                        if let Some(key) = key {
+                            self.locals.push((key.clone(), item_key));
+                        }
+                        self.locals.push((value.clone(), item));
+                        let result = self.render_nodes(name, body, depth);
+                        self.locals.truncate(locals);
+                        result?;
+                    }
+                }
+                Node::Include {
+                    name: include,
+                    line,
+                } => {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(render_error(
+                            *line,
+                            "includes are nested too deeply".to_string(),
+                        ));
+                    }
+                    self.render_template(include, depth + 1)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Undefined variables are an error when rendered, but simply false in conditions
+    fn evaluate(&self, expr: &Expr) -> Result<Value, String> {
+        match expr {
+            Expr::Path(segments) => self
+                .lookup(expr)
+                .ok_or_else(|| format!("'{}' is undefined", segments.join("."))),
+            Expr::Filter(inner, filter) => {
+                let value = self.evaluate(inner)?;
+                Ok(apply_filter(value, *filter))
+            }
+            _ => Ok(self.lookup(expr).unwrap_or(Value::Null)),
+        }
+    }
+
+    fn is_true(&self, expr: &Expr) -> bool {
+        self.lookup(expr).is_some_and(|value| is_truthy(&value))
+    }
+
+    fn lookup(&self, expr: &Expr) -> Option<Value> {
+        match expr {
+            Expr::Literal(value) => Some(value.clone()),
+            Expr::Path(segments) => {
+                let (first, rest) = segments.split_first()?;
+                let mut value = self
+                    .locals
+                    .iter()
+                    .rev()
+                    .find(|(name, _)| name == first)
+                    .map(|(_, value)| value)
+                    .or_else(|| self.context.get(first))?;
+                for segment in rest {
+                    value = match value {
+                        Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+                        value => value.get(segment)?,
+                    };
+                }
+                Some(value.clone())
+            }
+            Expr::Not(inner) => Some(Value::Bool(!self.is_true(inner))),
+            Expr::And(left, right) => Some(Value::Bool(self.is_true(left) && self.is_true(right))),
+            Expr::Or(left, right) => Some(Value::Bool(self.is_true(left) || self.is_true(right))),
+            Expr::Compare(left, operator, right) => {
+                let left = self.lookup(left).unwrap_or(Value::Null);
+                let right = self.lookup(right).unwrap_or(Value::Null);
+                Some(Value::Bool(compare(&left, *operator, &right)))
+            }
+            Expr::Filter(inner, filter) => Some(apply_filter(self.lookup(inner)?, *filter)),
+        }
+    }
+}
+
+fn is_safe(expr: &Expr) -> bool {
+    matches!(expr, Expr::Filter(_, Filter::Safe))
+}
+
+fn apply_filter(value: Value, filter: Filter) -> Value {
+    match filter {
+        Filter::Safe => value,
+        Filter::Upper => Value::String(to_text(&value).to_uppercase()),
+        Filter::Lower => Value::String(to_text(&value).to_lowercase()),
+        Filter::Trim => Value::String(to_text(&value).trim().to_string()),
+        Filter::Length => Value::from(match &value {
+            Value::Array(items) => items.len(),
+            Value::Object(map) => map.len(),
+            Value::String(text) => text.chars().count(),
+            _ => 0,
+        }),
+        Filter::Json => Value::String(value.to_string()),
+    }
+}
+
+fn compare(left: &Value, operator: CompareOp, right: &Value) -> bool {
+    let ordering = match (left, right) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64().partial_cmp(&b.as_f64()),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    };
+    match operator {
+        CompareOp::Eq => ordering.map_or(left == right, |ordering| ordering.is_eq()),
+        CompareOp::Ne => ordering.map_or(left != right, |ordering| ordering.is_ne()),
+        CompareOp::Lt => ordering.is_some_and(|ordering| ordering.is_lt()),
+        CompareOp::Le => ordering.is_some_and(|ordering| ordering.is_le()),
+        CompareOp::Gt => ordering.is_some_and(|ordering| ordering.is_gt()),
+        CompareOp::Ge => ordering.is_some_and(|ordering| ordering.is_ge()),
+    }
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(value) => *value,
+        Value::Number(number) => number.as_f64() != Some(0.0),
+        Value::String(text) => !text.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(map) => !map.is_empty(),
+    }
+}
+
+fn to_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn render(source: &str, context: Value) -> Result<String, TemplateError> {
+        let templates = Templates::new();
+        templates.add_template("test.html", source)?;
+        templates.render("test.html", &context)
+    }
+
+    // Empty directory of its own for each test
+    fn template_dir(test: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("surfer-templates-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("pages")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn escape_html_escapes_special_characters() {
+        assert_eq!(
+            escape_html(r#"<a href="x" title='y'>Tom & Jerry</a>"#),
+            "&lt;a href=&quot;x&quot; title=&#x27;y&#x27;&gt;Tom &amp; Jerry&lt;/a&gt;"
+        );
+        assert_eq!(escape_html("plain text"), "plain text");
+    }
+
+    #[test]
+    fn output_is_escaped_unless_marked_safe() {
+        let context = json!({ "html": "<script>alert('x')</script>" });
+        assert_eq!(
+            render("{{ html }}", context.clone()).unwrap(),
+            "&lt;script&gt;alert(&#x27;x&#x27;)&lt;/script&gt;"
+        );
+        assert_eq!(
+            render("{{ html | safe }}", context.clone()).unwrap(),
+            "<script>alert('x')</script>"
+        );
+        // Only `safe` as the last filter skips escaping
+        assert_eq!(
+            render("{{ html | safe | upper }}", context).unwrap(),
+            "&lt;SCRIPT&gt;ALERT(&#x27;X&#x27;)&lt;/SCRIPT&gt;"
+        );
+    }
+
+    #[test]
+    fn if_picks_the_first_true_branch() {
+        let source = "{% if role == \"admin\" %}admin{% elif role == \"user\" and not banned %}user{% else %}guest{% endif %}";
+        let cases = [
+            (json!({ "role": "admin" }), "admin"),
+            (json!({ "role": "user", "banned": false }), "user"),
+            (json!({ "role": "user", "banned": true }), "guest"),
+            (json!({}), "guest"),
+        ];
+        for (context, expected) in cases {
+            assert_eq!(render(source, context).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn for_sets_loop_variables() {
+        let source = "{% for item in items %}{{ loop.index }}/{{ loop.length }}:{{ item }}{% if loop.first %} first{% endif %}{% if loop.last %} last{% else %}, {% endif %}{% endfor %}";
+        assert_eq!(
+            render(source, json!({ "items": ["a", "b", "c"] })).unwrap(),
+            "1/3:a first, 2/3:b, 3/3:c last"
+        );
+
+        let source =
+            "{% for key, value in map %}{{ loop.index0 }}{{ key }}={{ value }};{% endfor %}";
+        assert_eq!(
+            render(source, json!({ "map": { "a": 1, "b": 2 } })).unwrap(),
+            "0a=1;1b=2;"
+        );
+    }
+
+    #[test]
+    fn for_else_renders_for_empty_or_missing_values() {
+        let source = "{% for item in items %}{{ item }}{% else %}empty{% endfor %}";
+        assert_eq!(render(source, json!({ "items": [] })).unwrap(), "empty");
+        assert_eq!(render(source, json!({})).unwrap(), "empty");
+
+        let error = render(source, json!({ "items": 5 })).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "failed to render 'test.html' at line 1: cannot iterate over a number"
+        );
+    }
+
+    #[test]
+    fn undefined_variables_fail_output_but_are_false_in_conditions() {
+        let error = render("line 1\n{{ user.name }}", json!({ "user": {} })).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "failed to render 'test.html' at line 2: 'user.name' is undefined"
+        );
+        assert!(render("{{ missing | safe }}", json!({})).is_err());
+        assert_eq!(
+            render("{% if missing %}yes{% else %}no{% endif %}", json!({})).unwrap(),
+            "no"
+        );
+        // Null is defined and renders as nothing
+        assert_eq!(
+            render("[{{ value }}]", json!({ "value": null })).unwrap(),
+            "[]"
+        );
+    }
+
+    #[test]
+    fn reports_syntax_errors_with_line() {
+        let error = render("a\n{% if x %}\nb", json!({})).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "syntax error in 'test.html' at line 2: unclosed 'if'"
+        );
+        assert!(render("{{ x | unknown }}", json!({})).is_err());
+        assert!(render("{% endfor %}", json!({})).is_err());
+    }
+
+    #[test]
+    fn includes_are_limited_in_depth() {
+        let templates = Templates::new();
+        templates
+            .add_template("header.html", "<h1>{{ title }}</h1>")
+            .unwrap();
+        templates
+            .add_template("page.html", "{% include \"header.html\" %}body")
+            .unwrap();
+        templates
+            .add_template("self.html", "{% include \"self.html\" %}")
+            .unwrap();
+
+        let context = json!({ "title": "Hi" });
+        assert_eq!(
+            templates.render("page.html", &context).unwrap(),
+            "<h1>Hi</h1>body"
+        );
+        let error = templates.render("self.html", &context).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "failed to render 'self.html' at line 1: includes are nested too deeply"
+        );
+    }
+
+    #[test]
+    fn include_paths_stay_inside_the_directory() {
+        let dir = template_dir("include-paths");
+        fs::write(dir.join("secret.txt"), "secret").unwrap();
+        fs::write(dir.join("pages/nav.html"), "nav").unwrap();
+        fs::write(
+            dir.join("pages/index.html"),
+            "{% include \"pages/nav.html\" %}",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("pages/escape.html"),
+            "{% include \"../secret.txt\" %}",
+        )
+        .unwrap();
+
+        let templates = Templates::from_dir(dir.join("pages")).unwrap();
+        let context = json!({});
+        assert!(matches!(
+            templates.render("index.html", &context),
+            Err(TemplateError::NotFound(name)) if name == "pages/nav.html"
+        ));
+        for name in ["../secret.txt", "/etc/passwd", "pages\\..\\..\\secret.txt"] {
+            assert!(matches!(
+                templates.render(name, &context),
+                Err(TemplateError::NotFound(_))
+            ));
+        }
+        assert!(matches!(
+            templates.render("escape.html", &context),
+            Err(TemplateError::NotFound(name)) if name == "../secret.txt"
+        ));
+
+        let templates = Templates::from_dir(&dir).unwrap();
+        assert_eq!(
+            templates.render("pages/index.html", &context).unwrap(),
+            "nav"
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn hot_reload_picks_up_changed_files() {
+        let dir = template_dir("hot-reload");
+        let path = dir.join("pages/index.html");
+        fs::write(&path, "old").unwrap();
+        let context = json!({});
+        let reloading = Templates::from_dir(&dir).unwrap().with_hot_reload(true);
+        let fixed = Templates::from_dir(&dir).unwrap().with_hot_reload(false);
+        assert_eq!(
+            reloading.render("pages/index.html", &context).unwrap(),
+            "old"
+        );
+
+        // Moves the modification time forward, file systems may not notice quick changes
+        fs::write(&path, "new").unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(
+            reloading.render("pages/index.html", &context).unwrap(),
+            "new"
+        );
+        assert_eq!(fixed.render("pages/index.html", &context).unwrap(), "old");
+
+        // New files show up as well
+        fs::write(dir.join("pages/about.html"), "about").unwrap();
+        assert_eq!(
+            reloading.render("pages/about.html", &context).unwrap(),
+            "about"
+        );
+        assert!(fixed.render("pages/about.html", &context).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,4 +1,5 @@
 use async_std::path::PathBuf;
+use chrono::{DateTime, NaiveDateTime, Utc};
 
 pub fn get_content_type(file_path: &PathBuf) -> &'static str {
     let ext = file_path
@@ -43,3 +44,79 @@ pub fn get_content_type(file_path: &PathBuf) -> &'static str {
     }
 }
 
+// Whether compressing a body of this type is worth it, i.e. it's text of some kind.
+// Images, archives, audio and video are compressed already.
+pub fn is_compressible(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/wasm"
+                | "application/x-ndjson"
+                | "image/svg+xml"
+        )
+}
+
+// Detects the content type of well-known binary formats from their leading bytes
+pub fn sniff_content_type(head: &[u8]) -> Option<&'static str> {
+    let signatures: [(&[u8], &str); 10] = [
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+        (b"Rar!\x1a\x07", "application/x-rar-compressed"),
+        (b"\x1a\x45\xdf\xa3", "video/x-matroska"),
+    ];
+    if let Some((_, content_type)) = signatures
+        .iter()
+        .find(|(signature, _)| head.starts_with(signature))
+    {
+        return Some(content_type);
+    }
+    if head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    if head.len() >= 12 && &head[4..8] == b"ftyp" {
+        return Some("video/mp4");
+    }
+    None
+}
+
+// IMF-fixdate as used by the Date, Expires and Last-Modified headers
+pub fn format_http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+// Accepts IMF-fixdate as well as the obsolete RFC 850 and asctime formats (RFC 9110, 5.6.7).
+// Cookie dates often use dashes instead of spaces, which is accepted as well.
+pub fn parse_http_date(date: &str) -> Option<DateTime<Utc>> {
+    let date = date.trim();
+    [
+        "%a, %d %b %Y %H:%M:%S GMT",
+        "%a, %d-%b-%Y %H:%M:%S GMT",
+        "%A, %d-%b-%y %H:%M:%S GMT",
+        "%a %b %e %H:%M:%S %Y",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(date, format).ok())
+    .map(|date| date.and_utc())
+}
+
+// Compares without returning early, so the time taken doesn't tell how much of a guess was right
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
@@ -0,0 +1,701 @@
+use crate::request::{FromRequest, Method, Request};
+use crate::response::{Response, StatusCode};
+use async_std::future::timeout;
+use async_std::io::{self, BufReader};
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use sha1::{Digest, Sha1};
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+// How long `WebSocket::close` waits for the client to acknowledge the close frame
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub mod close_code {
+    pub const NORMAL: u16 = 1000;
+    pub const GOING_AWAY: u16 = 1001;
+    pub const PROTOCOL_ERROR: u16 = 1002;
+    pub const UNSUPPORTED_DATA: u16 = 1003;
+    pub const INVALID_PAYLOAD: u16 = 1007;
+    pub const POLICY_VIOLATION: u16 = 1008;
+    pub const MESSAGE_TOO_BIG: u16 = 1009;
+    pub const INTERNAL_ERROR: u16 = 1011;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    // Pings are answered automatically, they are handed out for information only
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close(Option<CloseFrame>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+#[derive(Debug)]
+pub enum WebSocketError {
+    Io(io::Error),
+    Protocol(&'static str),
+    InvalidUtf8,
+    MessageTooLarge,
+    // The close handshake has already been started
+    Closed,
+}
+
+impl fmt::Display for WebSocketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebSocketError::Io(err) => write!(f, "websocket io error: {}", err),
+            WebSocketError::Protocol(reason) => write!(f, "websocket protocol error: {}", reason),
+            WebSocketError::InvalidUtf8 => write!(f, "websocket text message is not valid UTF-8"),
+            WebSocketError::MessageTooLarge => write!(f, "websocket message is too large"),
+            WebSocketError::Closed => write!(f, "websocket connection is closed"),
+        }
+    }
+}
+
+impl std::error::Error for WebSocketError {}
+
+impl From<io::Error> for WebSocketError {
+    fn from(err: io::Error) -> Self {
+        WebSocketError::Io(err)
+    }
+}
+
+// Opening handshake of a WebSocket connection, accepted with `on_upgrade`.
+//
+//   async fn chat(request: Request) -> Response {
+//       match WebSocketUpgrade::from_request(&request) {
+//           Ok(upgrade) => upgrade.on_upgrade(|mut socket| async move {
+//               while let Some(Ok(message)) = socket.recv().await {
+//                   ...
+//               }
+//           }),
+//           Err(response) => response,
+//       }
+//   }
+#[derive(Debug)]
+pub struct WebSocketUpgrade {
+    key: String,
+    offered_protocols: Vec<String>,
+    protocol: Option<String>,
+    max_message_size: usize,
+}
+
+impl FromRequest for WebSocketUpgrade {
+    fn from_request(request: &Request) -> Result<Self, Response> {
+        let reject = |reason: &str| {
+            Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header("Content-Type", "text/plain")
+                .body(format!("400 Bad Request: {}", reason))
+        };
+        let has_token = |header: &str, token: &str| {
+            request.header(header).is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|part| part.trim().eq_ignore_ascii_case(token))
+            })
+        };
+
+        if request.method != Method::GET.as_str() {
+            return Err(reject("WebSocket handshakes must use GET"));
+        }
+        if !has_token("Upgrade", "websocket") || !has_token("Connection", "upgrade") {
+            return Err(reject("missing 'Upgrade: websocket' header"));
+        }
+        if request.header("Sec-WebSocket-Version").map(str::trim) != Some("13") {
+            return Err(Response::builder()
+                .status(StatusCode::UPGRADE_REQUIRED)
+                .header("Sec-WebSocket-Version", "13")
+                .header("Content-Type", "text/plain")
+                .body("426 Upgrade Required: unsupported WebSocket version"));
+        }
+        let key = request
+            .header("Sec-WebSocket-Key")
+            .map(str::trim)
+            .filter(|key| BASE64.decode(key).is_ok_and(|key| key.len() == 16))
+            .ok_or_else(|| reject("invalid Sec-WebSocket-Key header"))?;
+
+        let offered_protocols = request
+            .header("Sec-WebSocket-Protocol")
+            .map(|protocols| {
+                protocols
+                    .split(',')
+                    .map(|protocol| protocol.trim().to_string())
+                    .filter(|protocol| !protocol.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(WebSocketUpgrade {
+            key: key.to_string(),
+            offered_protocols,
+            protocol: None,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        })
+    }
+}
+
+impl WebSocketUpgrade {
+    // Selects the first subprotocol offered by the client that is supported
+    pub fn protocols(mut self, supported: &[&str]) -> WebSocketUpgrade {
+        self.protocol = self
+            .offered_protocols
+            .iter()
+            .find(|offered| supported.contains(&offered.as_str()))
+            .cloned();
+        self
+    }
+
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    // Messages exceeding this are rejected and the connection is closed with 1009
+    pub fn max_message_size(mut self, max_message_size: usize) -> WebSocketUpgrade {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    pub fn on_upgrade<F, Fut>(self, handler: F) -> Response
+    where
+        F: FnOnce(WebSocket) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut builder = Response::builder()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
+            .header("Upgrade", "websocket")
+            .header("Connection", "Upgrade")
+            .header("Sec-WebSocket-Accept", accept_key(&self.key));
+        if let Some(protocol) = &self.protocol {
+            builder = builder.header("Sec-WebSocket-Protocol", protocol);
+        }
+        let max_message_size = self.max_message_size;
+        builder.build().with_upgrade(move |stream| async move {
+            handler(WebSocket::new(stream, max_message_size)).await
+        })
+    }
+}
+
+fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(WEBSOCKET_GUID.as_bytes());
+    BASE64.encode(hasher.finalize())
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+mod opcode {
+    pub const CONTINUATION: u8 = 0x0;
+    pub const TEXT: u8 = 0x1;
+    pub const BINARY: u8 = 0x2;
+    pub const CLOSE: u8 = 0x8;
+    pub const PING: u8 = 0x9;
+    pub const PONG: u8 = 0xA;
+}
+
+pub struct WebSocket {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    max_message_size: usize,
+    // Opcode and payload of a fragmented message that is still being received
+    fragments: Option<(u8, Vec<u8>)>,
+    close_sent: bool,
+    close_received: bool,
+}
+
+impl WebSocket {
+    fn new(stream: TcpStream, max_message_size: usize) -> WebSocket {
+        WebSocket {
+            reader: BufReader::new(stream.clone()),
+            writer: stream,
+            max_message_size,
+            fragments: None,
+            close_sent: false,
+            close_received: false,
+        }
+    }
+
+    // Next message from the client, `None` once the connection is closed.
+    // Protocol violations close the connection and are returned as an error.
+    pub async fn recv(&mut self) -> Option<Result<Message, WebSocketError>> {
+        if self.close_received {
+            return None;
+        }
+        match self.read_message().await {
+            Ok(message) => Some(Ok(message)),
+            Err(WebSocketError::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                self.close_received = true;
+                None
+            }
+            Err(err) => {
+                let code = match err {
+                    WebSocketError::InvalidUtf8 => Some(close_code::INVALID_PAYLOAD),
+                    WebSocketError::MessageTooLarge => Some(close_code::MESSAGE_TOO_BIG),
+                    WebSocketError::Protocol(_) => Some(close_code::PROTOCOL_ERROR),
+                    _ => None,
+                };
+                if let Some(code) = code {
+                    let _ = self.send_close(code, "").await;
+                }
+                self.close_received = true;
+                Some(Err(err))
+            }
+        }
+    }
+
+    pub async fn send(&mut self, message: Message) -> Result<(), WebSocketError> {
+        if self.close_sent {
+            return Err(WebSocketError::Closed);
+        }
+        match message {
+            Message::Text(text) => self.write_frame(opcode::TEXT, text.as_bytes()).await,
+            Message::Binary(data) => self.write_frame(opcode::BINARY, &data).await,
+            Message::Ping(data) => self.write_control(opcode::PING, &data).await,
+            Message::Pong(data) => self.write_control(opcode::PONG, &data).await,
+            Message::Close(frame) => match frame {
+                Some(frame) => self.send_close(frame.code, &frame.reason).await,
+                None => {
+                    self.close_sent = true;
+                    self.write_frame(opcode::CLOSE, &[]).await
+                }
+            },
+        }
+    }
+
+    pub async fn send_text(&mut self, text: impl Into<String>) -> Result<(), WebSocketError> {
+        self.send(Message::Text(text.into())).await
+    }
+
+    pub async fn send_binary(&mut self, data: impl Into<Vec<u8>>) -> Result<(), WebSocketError> {
+        self.send(Message::Binary(data.into())).await
+    }
+
+    // Starts the close handshake and waits for the client to confirm it
+    pub async fn close(mut self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        if !self.close_sent {
+            self.send_close(code, reason).await?;
+        }
+        let _ = timeout(CLOSE_TIMEOUT, async {
+            while !self.close_received {
+                if self.recv().await.is_none() {
+                    break;
+                }
+            }
+        })
+        .await;
+        Ok(())
+    }
+
+    async fn send_close(&mut self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        let mut payload = code.to_be_bytes().to_vec();
+        // Control frames are limited to 125 bytes, don't cut through a character
+        let mut reason_length = reason.len().min(123);
+        while !reason.is_char_boundary(reason_length) {
+            reason_length -= 1;
+        }
+        payload.extend_from_slice(&reason.as_bytes()[..reason_length]);
+        self.close_sent = true;
+        self.write_frame(opcode::CLOSE, &payload).await
+    }
+
+    async fn write_control(&mut self, opcode: u8, payload: &[u8]) -> Result<(), WebSocketError> {
+        if payload.len() > 125 {
+            return Err(WebSocketError::Protocol(
+                "control frame payloads are limited to 125 bytes",
+            ));
+        }
+        self.write_frame(opcode, payload).await
+    }
+
+    // Server frames are never masked
+    async fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> Result<(), WebSocketError> {
+        let mut frame = Vec::with_capacity(payload.len() + 10);
+        frame.push(0x80 | opcode);
+        match payload.len() {
+            length @ 0..=125 => frame.push(length as u8),
+            length @ 126..=0xFFFF => {
+                frame.push(126);
+                frame.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+            length => {
+                frame.push(127);
+                frame.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+        self.writer.write_all(&frame).await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    async fn read_message(&mut self) -> Result<Message, WebSocketError> {
+        loop {
+            let frame = self.read_frame().await?;
+            match frame.opcode {
+                opcode::PING => {
+                    if !self.close_sent {
+                        self.write_frame(opcode::PONG, &frame.payload).await?;
+                    }
+                    return Ok(Message::Ping(frame.payload));
+                }
+                opcode::PONG => return Ok(Message::Pong(frame.payload)),
+                opcode::CLOSE => {
+                    self.close_received = true;
+                    let close_frame = parse_close_payload(&frame.payload)?;
+                    // Echo the status code to complete the close handshake
+                    if !self.close_sent {
+                        let code = close_frame.as_ref().map(|close| close.code);
+                        match code {
+                            Some(code) => self.send_close(code, "").await?,
+                            None => {
+                                self.close_sent = true;
+                                self.write_frame(opcode::CLOSE, &[]).await?;
+                            }
+                        }
+                    }
+                    return Ok(Message::Close(close_frame));
+                }
+                opcode::TEXT | opcode::BINARY => {
+                    if self.fragments.is_some() {
+                        return Err(WebSocketError::Protocol(
+                            "new message started before the previous one was finished",
+                        ));
+                    }
+                    if frame.fin {
+                        return message_from(frame.opcode, frame.payload);
+                    }
+                    self.fragments = Some((frame.opcode, frame.payload));
+                }
+                opcode::CONTINUATION => {
+                    let Some((opcode, mut payload)) = self.fragments.take() else {
+                        return Err(WebSocketError::Protocol(
+                            "continuation frame without a message to continue",
+                        ));
+                    };
+                    if payload.len() + frame.payload.len() > self.max_message_size {
+                        return Err(WebSocketError::MessageTooLarge);
+                    }
+                    payload.extend_from_slice(&frame.payload);
+                    if frame.fin {
+                        return message_from(opcode, payload);
+                    }
+                    self.fragments = Some((opcode, payload));
+                }
+                _ => return Err(WebSocketError::Protocol("unknown opcode")),
+            }
+        }
+    }
+
+    async fn read_frame(&mut self) -> Result<Frame, WebSocketError> {
+        let mut header = [0; 2];
+        self.reader.read_exact(&mut header).await?;
+        let fin = header[0] & 0x80 != 0;
+        let opcode = header[0] & 0x0F;
+        let masked = header[1] & 0x80 != 0;
+
+        // No extensions are negotiated, so the reserved bits have to be zero
+        if header[0] & 0x70 != 0 {
+            return Err(WebSocketError::Protocol("reserved bits must not be set"));
+        }
+        if !masked {
+            return Err(WebSocketError::Protocol("client frames must be masked"));
+        }
+
+        let length = match header[1] & 0x7F {
+            126 => {
+                let mut length = [0; 2];
+                self.reader.read_exact(&mut length).await?;
+                u16::from_be_bytes(length) as u64
+            }
+            127 => {
+                let mut length = [0; 8];
+                self.reader.read_exact(&mut length).await?;
+                u64::from_be_bytes(length)
+            }
+            length => length as u64,
+        };
+        if opcode & 0x8 != 0 && (length > 125 || !fin) {
+            return Err(WebSocketError::Protocol(
+                "control frames must not be fragmented or longer than 125 bytes",
+            ));
+        }
+        let buffered = self.fragments.as_ref().map_or(0, |(_, data)| data.len());
+        if opcode & 0x8 == 0 && length + buffered as u64 > self.max_message_size as u64 {
+            return Err(WebSocketError::MessageTooLarge);
+        }
+
+        let mut mask = [0; 4];
+        self.reader.read_exact(&mut mask).await?;
+        let mut payload = vec![0; length as usize];
+        self.reader.read_exact(&mut payload).await?;
+        for (index, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[index % 4];
+        }
+
+        Ok(Frame {
+            fin,
+            opcode,
+            payload,
+        })
+    }
+}
+
+fn message_from(opcode: u8, payload: Vec<u8>) -> Result<Message, WebSocketError> {
+    if opcode == opcode::TEXT {
+        String::from_utf8(payload)
+            .map(Message::Text)
+            .map_err(|_| WebSocketError::InvalidUtf8)
+    } else {
+        Ok(Message::Binary(payload))
+    }
+}
+
+fn parse_close_payload(payload: &[u8]) -> Result<Option<CloseFrame>, WebSocketError> {
+    match payload.len() {
+        0 => Ok(None),
+        1 => Err(WebSocketError::Protocol("close frame payload is too short")),
+        _ => {
+            let code = u16::from_be_bytes([payload[0], payload[1]]);
+            let valid_code = matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999);
+            if !valid_code {
+                return Err(WebSocketError::Protocol("invalid close code"));
+            }
+            let reason = String::from_utf8(payload[2..].to_vec())
+                .map_err(|_| WebSocketError::InvalidUtf8)?;
+            Ok(Some(CloseFrame { code, reason }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::net::TcpListener;
+
+    // Server side of a fresh connection and the client's end of it
+    async fn connect(max_message_size: usize) -> (WebSocket, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (WebSocket::new(server, max_message_size), client)
+    }
+
+    fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![if fin { 0x80 | opcode } else { opcode }];
+        if payload.len() < 126 {
+            frame.push(0x80 | payload.len() as u8);
+        } else {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(index, byte)| byte ^ mask[index % 4]),
+        );
+        frame
+    }
+
+    async fn send_frames(client: &mut TcpStream, frames: &[Vec<u8>]) {
+        client.write_all(&frames.concat()).await.unwrap();
+    }
+
+    // First byte and payload of the next frame the server sent
+    async fn server_frame(client: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut header = [0; 2];
+        client.read_exact(&mut header).await.unwrap();
+        assert_eq!(header[1] & 0x80, 0, "server frames must not be masked");
+        let mut payload = vec![0; (header[1] & 0x7F) as usize];
+        client.read_exact(&mut payload).await.unwrap();
+        (header[0], payload)
+    }
+
+    async fn expect_close(client: &mut TcpStream, code: u16) {
+        let (first, payload) = server_frame(client).await;
+        assert_eq!(first, 0x80 | opcode::CLOSE);
+        assert_eq!(payload[..2], code.to_be_bytes());
+    }
+
+    #[test]
+    fn accept_key_matches_rfc_sample() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn handshake_answers_with_accept_key() {
+        let mut request = Request::new();
+        request.method = "GET".to_string();
+        for (name, value) in [
+            ("Upgrade", "websocket"),
+            ("Connection", "keep-alive, Upgrade"),
+            ("Sec-WebSocket-Version", "13"),
+            ("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="),
+            ("Sec-WebSocket-Protocol", "chat, superchat"),
+        ] {
+            request.headers.insert(name.to_string(), value.to_string());
+        }
+        let upgrade = WebSocketUpgrade::from_request(&request)
+            .unwrap()
+            .protocols(&["superchat"]);
+        assert_eq!(upgrade.protocol(), Some("superchat"));
+        let response = upgrade.on_upgrade(|_| async {});
+        assert_eq!(response.status_code, StatusCode::SWITCHING_PROTOCOLS);
+        assert_eq!(
+            response.header("Sec-WebSocket-Accept"),
+            Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+        );
+        assert_eq!(response.header("Sec-WebSocket-Protocol"), Some("superchat"));
+
+        request
+            .headers
+            .insert("Sec-WebSocket-Version".to_string(), "8".to_string());
+        let response = WebSocketUpgrade::from_request(&request).unwrap_err();
+        assert_eq!(response.status_code, StatusCode::UPGRADE_REQUIRED);
+
+        request
+            .headers
+            .insert("Sec-WebSocket-Version".to_string(), "13".to_string());
+        request
+            .headers
+            .insert("Sec-WebSocket-Key".to_string(), "c2hvcnQ=".to_string());
+        let response = WebSocketUpgrade::from_request(&request).unwrap_err();
+        assert_eq!(response.status_code, StatusCode::BAD_REQUEST);
+    }
+
+    #[async_std::test]
+    async fn rejects_unmasked_client_frames() {
+        let (mut socket, mut client) = connect(DEFAULT_MAX_MESSAGE_SIZE).await;
+        client.write_all(&[0x81, 0x02, b'h', b'i']).await.unwrap();
+        let error = socket.recv().await.unwrap().unwrap_err();
+        assert!(matches!(error, WebSocketError::Protocol(_)));
+        expect_close(&mut client, close_code::PROTOCOL_ERROR).await;
+        assert!(socket.recv().await.is_none());
+    }
+
+    #[async_std::test]
+    async fn reassembles_fragments_around_a_ping() {
+        let (mut socket, mut client) = connect(DEFAULT_MAX_MESSAGE_SIZE).await;
+        send_frames(
+            &mut client,
+            &[
+                client_frame(false, opcode::TEXT, b"Hel"),
+                client_frame(true, opcode::PING, b"are you there"),
+                client_frame(false, opcode::CONTINUATION, b"lo, "),
+                client_frame(true, opcode::CONTINUATION, "wörld".as_bytes()),
+            ],
+        )
+        .await;
+        let ping = socket.recv().await.unwrap().unwrap();
+        assert_eq!(ping, Message::Ping(b"are you there".to_vec()));
+        assert_eq!(
+            server_frame(&mut client).await,
+            (0x80 | opcode::PONG, b"are you there".to_vec())
+        );
+        let message = socket.recv().await.unwrap().unwrap();
+        assert_eq!(message, Message::Text("Hello, wörld".to_string()));
+    }
+
+    #[async_std::test]
+    async fn rejects_fragmented_control_frames() {
+        let (mut socket, mut client) = connect(DEFAULT_MAX_MESSAGE_SIZE).await;
+        send_frames(&mut client, &[client_frame(false, opcode::PING, b"ping")]).await;
+        let error = socket.recv().await.unwrap().unwrap_err();
+        assert!(matches!(error, WebSocketError::Protocol(_)));
+        expect_close(&mut client, close_code::PROTOCOL_ERROR).await;
+    }
+
+    #[async_std::test]
+    async fn rejects_oversized_control_frames() {
+        let (mut socket, mut client) = connect(DEFAULT_MAX_MESSAGE_SIZE).await;
+        send_frames(&mut client, &[client_frame(true, opcode::PING, &[0; 126])]).await;
+        let error = socket.recv().await.unwrap().unwrap_err();
+        assert!(matches!(error, WebSocketError::Protocol(_)));
+        expect_close(&mut client, close_code::PROTOCOL_ERROR).await;
+    }
+
+    #[async_std::test]
+    async fn closes_with_1009_for_messages_over_the_limit() {
+        let (mut socket, mut client) = connect(8).await;
+        send_frames(&mut client, &[client_frame(true, opcode::BINARY, &[0; 8])]).await;
+        assert_eq!(
+            socket.recv().await.unwrap().unwrap(),
+            Message::Binary(vec![0; 8])
+        );
+
+        // The limit covers all fragments together
+        send_frames(
+            &mut client,
+            &[
+                client_frame(false, opcode::TEXT, b"12345"),
+                client_frame(true, opcode::CONTINUATION, b"6789"),
+            ],
+        )
+        .await;
+        let error = socket.recv().await.unwrap().unwrap_err();
+        assert!(matches!(error, WebSocketError::MessageTooLarge));
+        expect_close(&mut client, close_code::MESSAGE_TOO_BIG).await;
+    }
+
+    #[async_std::test]
+    async fn closes_with_1007_for_invalid_utf8() {
+        let (mut socket, mut client) = connect(DEFAULT_MAX_MESSAGE_SIZE).await;
+        send_frames(
+            &mut client,
+            &[client_frame(true, opcode::TEXT, &[0xff, 0xfe])],
+        )
+        .await;
+        let error = socket.recv().await.unwrap().unwrap_err();
+        assert!(matches!(error, WebSocketError::InvalidUtf8));
+        expect_close(&mut client, close_code::INVALID_PAYLOAD).await;
+    }
+
+    #[async_std::test]
+    async fn echoes_the_close_code() {
+        let (mut socket, mut client) = connect(DEFAULT_MAX_MESSAGE_SIZE).await;
+        let mut payload = close_code::GOING_AWAY.to_be_bytes().to_vec();
+        payload.extend_from_slice(b"bye");
+        send_frames(&mut client, &[client_frame(true, opcode::CLOSE, &payload)]).await;
+        let message = socket.recv().await.unwrap().unwrap();
+        assert_eq!(
+            message,
+            Message::Close(Some(CloseFrame {
+                code: close_code::GOING_AWAY,
+                reason: "bye".to_string(),
+            }))
+        );
+        expect_close(&mut client, close_code::GOING_AWAY).await;
+        assert!(socket.recv().await.is_none());
+        assert!(matches!(
+            socket.send_text("late").await,
+            Err(WebSocketError::Closed)
+        ));
+    }
+}